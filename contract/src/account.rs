@@ -69,7 +69,7 @@ impl Account {
         }
     }
 
-    fn assert_storage_covered(&self) {
+    pub fn is_storage_covered(&self) -> bool {
         let shared_bytes_used = self
             .shared_storage
             .as_ref()
@@ -77,10 +77,11 @@ impl Account {
             .unwrap_or(0);
         let storage_balance_needed =
             Balance::from(self.used_bytes - shared_bytes_used) * env::storage_byte_cost();
-        assert!(
-            storage_balance_needed <= self.storage_balance,
-            "Not enough storage balance"
-        );
+        storage_balance_needed <= self.storage_balance
+    }
+
+    fn assert_storage_covered(&self) {
+        assert!(self.is_storage_covered(), "Not enough storage balance");
     }
 }
 
@@ -115,7 +116,11 @@ impl Contract {
         );
         self.internal_get_account(account_id)
             .map(|mut a| {
-                a.storage_balance += storage_deposit;
+                if storage_deposit > 0 {
+                    a.storage_balance += storage_deposit;
+                    Event::StorageDeposit(vec![StorageEventData::new(account_id, storage_deposit)])
+                        .emit();
+                }
                 a
             })
             .unwrap_or_else(|| {
@@ -145,6 +150,11 @@ impl Contract {
         } else {
             account.storage_balance = storage_deposit;
         }
+        Event::StorageDeposit(vec![StorageEventData::new(
+            account_id,
+            account.storage_balance,
+        )])
+        .emit();
 
         self.internal_initial_set_account(account_id, account);
     }
@@ -180,9 +190,9 @@ impl Contract {
         account.storage_tracker.start();
         self.internal_set_node(Node::new(account.node_id, None));
         self.root_node.block_height = env::block_height();
+        self.root_node.timestamp = Some(env::block_timestamp());
         self.root_node
-            .children
-            .insert(&account_id.to_string(), &NodeValue::Node(account.node_id));
+            .insert_child(&account_id.to_string(), &NodeValue::Node(account.node_id));
         let mut temp_account = Account::new(account.node_id);
         temp_account.shared_storage = account.shared_storage.clone();
         require!(
@@ -191,6 +201,7 @@ impl Contract {
         );
         account.storage_tracker.stop();
         self.internal_set_account(account);
+        self.internal_append_journal(account_id, vec![(0, account_id.to_string())]);
     }
 
     pub fn internal_set_account(&mut self, mut account: Account) -> bool {
@@ -282,6 +293,8 @@ impl Contract {
                 account.storage_balance -= amount;
                 self.internal_set_account(account);
                 Promise::new(env::predecessor_account_id()).transfer(amount);
+                Event::StorageWithdraw(vec![StorageEventData::new(withdraw_from.as_str(), amount)])
+                    .emit();
             }
             self.internal_storage_balance_of(&withdraw_from).unwrap()
         } else {
@@ -311,6 +324,11 @@ impl StorageManagement for Contract {
             } else {
                 account.storage_balance += attached_deposit;
                 self.internal_set_account(account);
+                Event::StorageDeposit(vec![StorageEventData::new(
+                    account_id.as_str(),
+                    attached_deposit,
+                )])
+                .emit();
             }
         } else {
             self.internal_create_account(account_id.as_str(), attached_deposit, registration_only);
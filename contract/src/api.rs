@@ -1,23 +1,79 @@
 use crate::*;
-use near_sdk::require;
+use near_sdk::json_types::U64;
 use near_sdk::serde_json::map::Entry;
 use near_sdk::serde_json::{Map, Value};
-use near_sdk::json_types::U64;
-use std::collections::HashSet;
+use near_sdk::{require, Timestamp};
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
 pub const MAX_KEY_LENGTH: usize = 256;
 pub const SEPARATOR: char = '/';
 pub const STAR: &str = "*";
 pub const RECURSIVE_STAR: &str = "**";
 pub const KEY_BLOCK_HEIGHT: &str = ":block";
+/// The timestamp of the block when a value or a node was last updated, as a decimal string.
+pub const KEY_TIMESTAMP: &str = ":timestamp";
+/// The permission key that authorized the write of a value by another account or key.
+pub const KEY_AUTHOR: &str = ":author";
 pub const KEY_NODE_ID: &str = ":node";
+/// The key that marks a node that was not expanded because of the `max_depth` option.
+pub const KEY_TRUNCATED: &str = ":truncated";
+/// The key for the number of children of a node.
+pub const KEY_CHILDREN: &str = ":children";
+/// The number of bytes of a value, or of all the keys and the values in the subtree of a node.
+pub const KEY_SIZE: &str = ":size";
+/// The only key of an object that represents a binary leaf value encoded in base64.
+pub const KEY_BASE64: &str = ":base64";
+/// The key of an object that makes the written object replace the node instead of being merged
+/// into it, e.g. `{":replace": true, "name": "Alex"}`.
+pub const KEY_REPLACE: &str = ":replace";
+/// The key of an object that makes the node maintain the lexicographic order of its children, e.g.
+/// `{":ordered": true, "2024-01-15": "..."}`. The value `false` removes the order.
+pub const KEY_ORDERED: &str = ":ordered";
+/// The key of an object that makes the node maintain an index of its children by the block height
+/// of their last change, e.g. `{":recent": true}`. The value `false` removes the index.
+pub const KEY_RECENT: &str = ":recent";
+/// The separator of the bounds of a range key pattern, e.g. `[2024-01..2024-02]`.
+pub const RANGE_SEPARATOR: &str = "..";
+/// The brackets of a join key pattern, e.g. `{alice.near/graph/follow/*}/post/main`.
+pub const JOIN_START: char = '{';
+pub const JOIN_END: char = '}';
 
 #[derive(Serialize, Deserialize, Default)]
 #[serde(crate = "near_sdk::serde")]
 pub struct GetOptions {
     pub with_block_height: Option<bool>,
+    /// Whether to add the timestamp of the block when every value and node was last updated. It's
+    /// not added for the entries that were last written before `0.13.0`.
+    pub with_timestamp: Option<bool>,
+    /// Whether to add the permission key that authorized the write of every value, if it was not
+    /// written by the account itself.
+    pub with_author: Option<bool>,
     pub with_node_id: Option<bool>,
+    /// Whether to add the number of bytes of every value and the cumulative number of bytes of
+    /// every node, except the root node.
+    pub with_size: Option<bool>,
+    /// Whether to add the number of children of every node.
+    pub with_child_count: Option<bool>,
     pub return_deleted: Option<bool>,
+    /// Whether to return a list of path records instead of the nested object.
+    pub flat: Option<bool>,
+    /// The maximum number of levels matched by `**`. The nodes at the last level are returned
+    /// with the `:truncated` marker and the number of their children.
+    pub max_depth: Option<u32>,
+    /// If provided, only the first `limit` matches in this order are returned.
+    pub order_by: Option<OrderBy>,
+    /// The maximum number of matches to return. Required with `order_by`. Without `order_by`, it's
+    /// the maximum number of keys matched by each range key pattern.
+    pub limit: Option<u32>,
+    /// The key patterns of the entries to skip, e.g. `alice.near/widget/**`.
+    pub exclude: Option<Vec<String>>,
+    /// The gas after which the traversal stops and the matches found so far are returned with the
+    /// `:incomplete` marker. Defaults to the prepaid gas without `GAS_RESERVED_FOR_RESULT`.
+    pub gas_budget: Option<U64>,
+    /// The `:cursor` of an incomplete result to resume the traversal from.
+    pub cursor: Option<String>,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
@@ -26,6 +82,8 @@ pub enum KeysReturnType {
     True,
     BlockHeight,
     NodeId,
+    /// The timestamp of the block as a decimal string, or `null` if it's unknown.
+    Timestamp,
 }
 
 #[derive(Serialize, Deserialize, Default)]
@@ -37,6 +95,32 @@ pub struct KeysOptions {
     pub return_deleted: Option<bool>,
     /// Whether to match nodes.
     pub values_only: Option<bool>,
+    /// Whether to return a list of path records instead of the nested object. The return type is
+    /// ignored, since the records contain both the block height and the node ID.
+    pub flat: Option<bool>,
+    /// If provided, only the first `limit` matches in this order are returned.
+    pub order_by: Option<OrderBy>,
+    /// The maximum number of matches to return. Required with `order_by`. Without `order_by`, it's
+    /// the maximum number of keys matched by each range key pattern.
+    pub limit: Option<u32>,
+    /// The key patterns of the entries to skip, e.g. `alice.near/widget/**`.
+    pub exclude: Option<Vec<String>>,
+    /// The gas after which the traversal stops and the matches found so far are returned with the
+    /// `:incomplete` marker. Defaults to the prepaid gas without `GAS_RESERVED_FOR_RESULT`.
+    pub gas_budget: Option<U64>,
+    /// The `:cursor` of an incomplete result to resume the traversal from.
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CountOptions {
+    /// Whether to count deleted values.
+    pub return_deleted: Option<bool>,
+    /// Whether to count only values, but not nodes without their own values.
+    pub values_only: Option<bool>,
+    /// The key patterns of the entries to skip, e.g. `alice.near/widget/**`.
+    pub exclude: Option<Vec<String>>,
 }
 
 #[derive(Copy, Clone, Serialize, Deserialize)]
@@ -51,6 +135,26 @@ pub struct SetOptions {
     pub refund_unused_deposit: Option<bool>,
 }
 
+/// The state of a single account write that is shared by all levels of `recursive_set`.
+pub struct SetContext {
+    /// The nodes that the predecessor was granted the write permission on.
+    pub writable_nodes: HashMap<NodeId, PermissionKey>,
+    pub blob_changes: BlobChanges,
+    /// The IDs of the nodes that were removed with their subtrees.
+    pub removed_node_ids: Vec<NodeId>,
+    /// The path of the node that is being written, starting with the account ID.
+    pub path: Vec<String>,
+    /// Schemas that apply to the account.
+    pub schemas: Vec<AccountSchema>,
+    pub limits: Limits,
+    /// The paths of the written values for the `set` event.
+    pub written_paths: Vec<String>,
+    /// The paths of the deleted values and subtrees for the `delete` event.
+    pub deleted_paths: Vec<String>,
+    /// The changed keys of the nodes for the journal.
+    pub changed_keys: Vec<(NodeId, String)>,
+}
+
 #[near_bindgen]
 impl Contract {
     /// ```js
@@ -67,22 +171,45 @@ impl Contract {
     /// ```
     pub fn get(self, keys: Vec<String>, options: Option<GetOptions>) -> Value {
         let options = options.unwrap_or_default();
+        require!(options.max_depth != Some(0), "max_depth should be positive");
+        let mut budget = GasBudget::new(options.gas_budget, options.cursor.as_deref());
+        let keys = self.internal_expand_joins(&keys, &mut budget);
+        let exclusions = self.internal_get_exclusions(&options.exclude, &mut budget);
+        if options.order_by.is_some() {
+            return self.internal_get_recent_values(&keys, &options, exclusions, budget);
+        }
+        let flat = options.flat.unwrap_or(false);
+        let mut ctx = MatchContext::new(exclusions, budget);
         let mut res: Map<String, Value> = Map::new();
-        for key in keys {
-            let mut path: Vec<&str> = key.split(SEPARATOR).collect();
-            if path.last() == Some(&EMPTY_KEY) {
-                path.pop();
-                if path.last() == Some(&EMPTY_KEY) {
-                    continue;
-                }
+        let mut records = vec![];
+        let start = ctx.budget.enter_level();
+        for (index, key) in keys.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                break;
             }
-            if path.is_empty() {
-                continue;
+            let path = match parse_pattern(&key) {
+                Some(path) => path,
+                None => continue,
+            };
+            let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+            if flat {
+                self.recursive_get_flat(
+                    &mut records,
+                    &mut ctx,
+                    &self.root_node,
+                    &path,
+                    &options,
+                    0,
+                );
+            } else {
+                self.recursive_get(&mut res, &self.root_node, &path[..], &options, 0, &mut ctx)
             }
-            self.recursive_get(&mut res, &self.root_node, &path[..], &options)
+        }
+        if flat {
+            return partial_result(records_to_json(records), &ctx.budget);
         }
         json_map_recursive_cleanup(&mut res);
-        Value::Object(res)
+        partial_result(Value::Object(res), &ctx.budget)
     }
 
     /// ```js
@@ -100,22 +227,71 @@ impl Contract {
     /// ```
     pub fn keys(self, keys: Vec<String>, options: Option<KeysOptions>) -> Value {
         let options = options.unwrap_or_default();
+        let mut budget = GasBudget::new(options.gas_budget, options.cursor.as_deref());
+        let keys = self.internal_expand_joins(&keys, &mut budget);
+        let exclusions = self.internal_get_exclusions(&options.exclude, &mut budget);
+        if options.order_by.is_some() {
+            return self.internal_get_recent_keys(&keys, &options, exclusions, budget);
+        }
+        let flat = options.flat.unwrap_or(false);
+        let mut ctx = MatchContext::new(exclusions, budget);
         let mut res: Map<String, Value> = Map::new();
-        for key in keys {
-            let mut path: Vec<&str> = key.split(SEPARATOR).collect();
-            if path.last() == Some(&EMPTY_KEY) {
-                path.pop();
-                if path.last() == Some(&EMPTY_KEY) {
-                    continue;
-                }
+        let mut records = vec![];
+        let start = ctx.budget.enter_level();
+        for (index, key) in keys.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                break;
             }
-            if path.is_empty() {
-                continue;
+            let path = match parse_pattern(&key) {
+                Some(path) => path,
+                None => continue,
+            };
+            let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+            if flat {
+                self.recursive_keys_flat(&mut records, &mut ctx, &self.root_node, &path, &options);
+            } else {
+                self.recursive_keys(&mut res, &self.root_node, &path[..], &options, &mut ctx)
             }
-            self.recursive_keys(&mut res, &self.root_node, &path[..], &options)
+        }
+        if flat {
+            return partial_result(records_to_json(records), &ctx.budget);
         }
         json_map_recursive_cleanup(&mut res);
-        Value::Object(res)
+        partial_result(Value::Object(res), &ctx.budget)
+    }
+
+    /// Returns the number of entries matching each of the key patterns, the same entries that
+    /// `keys` returns. The counts of the patterns produced by a join are summed. The number of
+    /// children of a node, e.g. `bob.near/graph/follow/*`, is cached and returned without iterating
+    /// the children, unless some of them are excluded.
+    ///
+    /// ```js
+    /// count({keys: [
+    ///   "bob.near/graph/follow/*",
+    ///   "*/graph/follow/bob.near",
+    /// ]})
+    /// ```
+    pub fn count(self, keys: Vec<String>, options: Option<CountOptions>) -> Vec<u64> {
+        let options = options.unwrap_or_default();
+        let mut budget = GasBudget::default();
+        let exclusions = self.internal_get_exclusions(&options.exclude, &mut budget);
+        let mut ctx = MatchContext::new(exclusions, budget);
+        keys.iter()
+            .map(|key| {
+                parse_patterns(&self.internal_expand_join(key, &mut ctx.budget))
+                    .into_iter()
+                    .map(|path| {
+                        let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+                        self.recursive_count(&self.root_node, &path, &options, &mut ctx)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Returns the canonical escaped form of the given key, e.g. `é` becomes `%C3%A9`.
+    pub fn get_canonical_key(&self, key: String) -> String {
+        canonicalize_key(&key)
     }
 
     /// ```js
@@ -147,21 +323,11 @@ impl Contract {
     pub fn set(&mut self, mut data: Value, options: Option<SetOptions>) -> SetReturnType {
         self.assert_live();
         let options = options.unwrap_or_default();
-        let predecessor_account_id = env::predecessor_account_id();
         let mut attached_balance = env::attached_deposit();
+        self.assert_keys_within_limits(&data);
+        let mut changes = ChangedPaths::default();
         for (key, value) in data.as_object_mut().expect("Data is not a JSON object") {
-            let mut account = self.internal_unwrap_account_or_create(key, attached_balance);
-            let write_approved = key == predecessor_account_id.as_str();
-            let writable_node_ids = if write_approved {
-                HashSet::new()
-            } else {
-                account.internal_get_writeable_node_ids()
-            };
-            let node = self.internal_unwrap_node(account.node_id);
-            account.storage_tracker.start();
-            self.recursive_set(node, value, write_approved, &writable_node_ids);
-            account.storage_tracker.stop();
-            self.internal_set_account(account);
+            self.internal_set_data(key, value, attached_balance, &mut changes);
 
             if options.refund_unused_deposit.unwrap_or(false) && attached_balance > 0 {
                 // The key is the account id that received the deposit.
@@ -179,52 +345,157 @@ impl Contract {
         if attached_balance > 0 {
             env::panic_str("The attached deposit could not be added to any account");
         }
-        SetReturnType { block_height: U64(near_sdk::env::block_height()) }
+        self.internal_notify_subscribers(&changes);
+        changes.emit();
+        SetReturnType {
+            block_height: U64(near_sdk::env::block_height()),
+        }
+    }
+
+    /// Writes the list of values given by their full paths, e.g.
+    ///
+    /// ```js
+    /// set_paths({
+    ///   entries: [
+    ///     ["alex.near/profile/name", "Alex"],
+    ///     ["alex.near/profile/image/url", "https://gkfjklgdfjkldfg"],
+    ///     ["alex.near/graph/follow/bob.near", null],
+    ///   ]
+    /// })
+    /// ```
+    ///
+    /// The paths are combined into a single object that is written the same way as `set` writes
+    /// it. A `null` value deletes the key. If a path points to a value that is also a prefix of
+    /// another path, the value is stored under the empty key. If the same path is given more than
+    /// once, the last value is written.
+    #[payable]
+    pub fn set_paths(
+        &mut self,
+        entries: Vec<(String, Option<String>)>,
+        options: Option<SetOptions>,
+    ) -> SetReturnType {
+        let mut data = Map::new();
+        for (path, value) in entries {
+            let mut keys: Vec<String> = path.split(SEPARATOR).map(|k| k.to_string()).collect();
+            let last_key = keys.pop().unwrap();
+            let obj = keys
+                .into_iter()
+                .fold(&mut data, |obj, key| json_map_get_inner_object(obj, key));
+            let value = value.map(Value::String).unwrap_or(Value::Null);
+            match obj.get_mut(&last_key) {
+                Some(Value::Object(o)) => {
+                    o.insert(EMPTY_KEY.to_string(), value);
+                }
+                _ => {
+                    obj.insert(last_key, value);
+                }
+            }
+        }
+        self.set(Value::Object(data), options)
     }
 }
 
 impl Contract {
+    /// Writes the given data under the account ID on behalf of the predecessor. The attached
+    /// balance is added to the storage balance of the account. The changed paths are added to
+    /// `changes`.
+    pub fn internal_set_data(
+        &mut self,
+        account_id: &str,
+        value: &mut Value,
+        attached_balance: Balance,
+        changes: &mut ChangedPaths,
+    ) {
+        let mut account = self.internal_unwrap_account_or_create(account_id, attached_balance);
+        let approval = WriteApproval::for_account(account_id);
+        let writable_nodes = if approval.is_approved() {
+            HashMap::new()
+        } else {
+            account.internal_get_writable_nodes()
+        };
+        let node = self.internal_unwrap_node(account.node_id);
+        let mut ctx = SetContext {
+            writable_nodes,
+            blob_changes: Default::default(),
+            removed_node_ids: vec![],
+            path: vec![account_id.to_string()],
+            schemas: self.internal_get_account_schemas(account_id),
+            limits: self.limits.clone(),
+            written_paths: vec![],
+            deleted_paths: vec![],
+            changed_keys: vec![],
+        };
+        account.storage_tracker.start();
+        self.recursive_set(node, value, &approval, &mut ctx);
+        account.storage_tracker.stop();
+        account.internal_remove_node_permissions(&ctx.removed_node_ids);
+        self.internal_set_account(account);
+        self.internal_apply_blob_changes(account_id, ctx.blob_changes);
+        self.internal_append_journal(account_id, ctx.changed_keys);
+        changes
+            .written
+            .push(PathsEventData::new(account_id, ctx.written_paths));
+        changes
+            .deleted
+            .push(PathsEventData::new(account_id, ctx.deleted_paths));
+    }
+
+    /// The `depth` is the number of levels above the node that were matched by `**`.
     pub fn recursive_get(
         &self,
         res: &mut Map<String, Value>,
         node: &Node,
         keys: &[&str],
         options: &GetOptions,
+        depth: u32,
+        ctx: &mut MatchContext,
     ) {
         let is_recursive_match_all = keys[0] == RECURSIVE_STAR;
         if is_recursive_match_all {
             require!(keys.len() == 1, "'**' pattern can only be used as a suffix")
         }
-        let matched_entries = if keys[0] == STAR || is_recursive_match_all {
+        let matched_entries = if is_recursive_match_all {
             node.children.to_vec()
         } else {
-            let key = keys[0].to_string();
-            if let Some(value) = node.children.get(&key) {
-                vec![(key, value)]
-            } else {
-                vec![]
-            }
+            match_children(node, keys[0], options.limit)
         };
-        if options.with_block_height == Some(true) {
-            res.insert(KEY_BLOCK_HEIGHT.to_string(), node.block_height.into());
-        }
-        if options.with_node_id == Some(true) {
-            res.insert(KEY_NODE_ID.to_string(), node.node_id.into());
-        }
-        for (key, value) in matched_entries {
+        self.json_map_set_node_metadata(res, node, options, ctx);
+        let is_match = keys.len() == 1 && !is_recursive_match_all;
+        let start = ctx.budget.enter_level();
+        for (index, (key, value)) in matched_entries.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                return;
+            }
+            if ctx.is_entry_excluded(&key, &value, is_match) {
+                continue;
+            }
             match value {
                 NodeValue::Node(node_id) => {
                     let inner_node = self.internal_unwrap_node(node_id);
                     if keys.len() > 1 || is_recursive_match_all {
                         // Going deeper
+                        ctx.path.push(key.clone());
                         let inner_map = json_map_get_inner_object(res, key);
                         if keys.len() > 1 {
-                            self.recursive_get(inner_map, &inner_node, &keys[1..], options);
+                            let keys = &keys[1..];
+                            self.recursive_get(inner_map, &inner_node, keys, options, 0, ctx);
                         }
                         if is_recursive_match_all {
-                            // Non skipping step in.
-                            self.recursive_get(inner_map, &inner_node, keys, options);
+                            if options.max_depth.map_or(false, |d| depth + 1 >= d) {
+                                self.json_map_set_truncated(inner_map, &inner_node, options, ctx);
+                            } else {
+                                // Non skipping step in.
+                                self.recursive_get(
+                                    inner_map,
+                                    &inner_node,
+                                    keys,
+                                    options,
+                                    depth + 1,
+                                    ctx,
+                                );
+                            }
                         }
+                        ctx.path.pop();
                     } else {
                         if let Some(node_value) = inner_node.children.get(&EMPTY_KEY.to_string()) {
                             if options.with_node_id == Some(true) {
@@ -232,6 +503,20 @@ impl Contract {
                                 inner_map
                                     .insert(KEY_NODE_ID.to_string(), inner_node.node_id.into());
                             }
+                            if options.with_size == Some(true) {
+                                if let Some(size) = self.internal_get_subtree_size(&inner_node, ctx)
+                                {
+                                    let inner_map = json_map_get_inner_object(res, key.clone());
+                                    inner_map.insert(KEY_SIZE.to_string(), size.into());
+                                }
+                            }
+                            if options.with_child_count == Some(true) {
+                                let include_deleted = options.return_deleted.unwrap_or(false);
+                                let num_children = inner_node.num_children(include_deleted);
+                                let inner_map = json_map_get_inner_object(res, key.clone());
+                                inner_map.insert(KEY_CHILDREN.to_string(), num_children.into());
+                            }
+                            let node_value = self.internal_resolve_node_value(node_value);
                             json_map_set_key(res, key, node_value, &options);
                         } else {
                             // mismatch skipping
@@ -240,11 +525,158 @@ impl Contract {
                 }
                 node_value => {
                     if keys.len() == 1 {
+                        let node_value = self.internal_resolve_node_value(node_value);
                         json_map_set_key(res, key, node_value, &options);
                     }
                 }
             }
         }
+        ctx.budget.leave_level();
+    }
+
+    /// Marks the node as truncated, and adds its own value and the number of its children.
+    fn json_map_set_truncated(
+        &self,
+        res: &mut Map<String, Value>,
+        node: &Node,
+        options: &GetOptions,
+        ctx: &mut MatchContext,
+    ) {
+        res.insert(KEY_TRUNCATED.to_string(), true.into());
+        let include_deleted = options.return_deleted.unwrap_or(false);
+        res.insert(
+            KEY_CHILDREN.to_string(),
+            node.num_children(include_deleted).into(),
+        );
+        self.json_map_set_node_metadata(res, node, options, ctx);
+        if let Some(node_value) = node.children.get(&EMPTY_KEY.to_string()) {
+            let node_value = self.internal_resolve_node_value(node_value);
+            json_map_set_key(res, EMPTY_KEY.to_string(), node_value, options);
+        }
+    }
+
+    /// Adds the metadata of the node that is requested by the options.
+    fn json_map_set_node_metadata(
+        &self,
+        res: &mut Map<String, Value>,
+        node: &Node,
+        options: &GetOptions,
+        ctx: &mut MatchContext,
+    ) {
+        if options.with_block_height == Some(true) {
+            res.insert(KEY_BLOCK_HEIGHT.to_string(), node.block_height.into());
+        }
+        if options.with_timestamp == Some(true) {
+            if let Some(timestamp) = node.timestamp {
+                res.insert(KEY_TIMESTAMP.to_string(), timestamp.to_string().into());
+            }
+        }
+        if options.with_node_id == Some(true) {
+            res.insert(KEY_NODE_ID.to_string(), node.node_id.into());
+        }
+        // The size of the root node is the size of the whole contract data.
+        if options.with_size == Some(true) && node.node_id != 0 {
+            if let Some(size) = self.internal_get_subtree_size(node, ctx) {
+                res.insert(KEY_SIZE.to_string(), size.into());
+            }
+        }
+        if options.with_child_count == Some(true) {
+            let include_deleted = options.return_deleted.unwrap_or(false);
+            res.insert(
+                KEY_CHILDREN.to_string(),
+                node.num_children(include_deleted).into(),
+            );
+        }
+    }
+
+    /// Expands the join patterns of the given key patterns.
+    pub fn internal_expand_joins(&self, keys: &[String], budget: &mut GasBudget) -> Vec<String> {
+        keys.iter()
+            .flat_map(|key| self.internal_expand_join(key, budget))
+            .collect()
+    }
+
+    /// Expands a key pattern with a join segment, e.g. `{alice.near/graph/follow/*}/post/main`,
+    /// into the patterns where the join segment is replaced with the last key of every entry
+    /// matching the inner pattern, the same entries that `keys` returns. The inner pattern can
+    /// contain joins as well. A pattern without joins is returned as is.
+    ///
+    /// The inner patterns are traversed within the given budget. When it's exceeded, the budget is
+    /// marked as incomplete without a cursor, and only the entries matched so far are joined.
+    pub fn internal_expand_join(&self, key: &str, budget: &mut GasBudget) -> Vec<String> {
+        let (prefix, inner, suffix) = match split_join(key) {
+            Some(parts) => parts,
+            None => return vec![key.to_string()],
+        };
+        let mut records = vec![];
+        let mut ctx = MatchContext::new(Exclusions::default(), budget.nested());
+        for path in parse_patterns(&self.internal_expand_join(inner, budget)) {
+            let path: Vec<&str> = path.iter().map(|k| k.as_str()).collect();
+            let options = KeysOptions::default();
+            self.recursive_keys_flat(&mut records, &mut ctx, &self.root_node, &path, &options);
+        }
+        if ctx.budget.incomplete {
+            budget.incomplete = true;
+        }
+        let mut matched_keys = HashSet::new();
+        records
+            .into_iter()
+            .filter_map(|record| {
+                let matched_key = record.path.rsplit(SEPARATOR).next().unwrap().to_string();
+                if matched_keys.insert(matched_key.clone()) {
+                    Some(matched_key)
+                } else {
+                    None
+                }
+            })
+            .flat_map(|matched_key| {
+                let key = format!("{}{}{}", prefix, matched_key, suffix);
+                self.internal_expand_join(&key, budget)
+            })
+            .collect()
+    }
+
+    pub fn recursive_count(
+        &self,
+        node: &Node,
+        keys: &[&str],
+        options: &CountOptions,
+        ctx: &mut MatchContext,
+    ) -> u64 {
+        let return_deleted = options.return_deleted.unwrap_or(false);
+        let values_only = options.values_only.unwrap_or(false);
+        if keys == [STAR] && !values_only && ctx.exclusions.is_empty() {
+            // The number of children is cached in the node.
+            return node.num_children(return_deleted);
+        }
+        let mut count = 0;
+        for (key, value) in match_children(node, keys[0], None) {
+            if ctx.is_entry_excluded(&key, &value, keys.len() == 1) {
+                continue;
+            }
+            count += match value {
+                NodeValue::Node(node_id) => {
+                    let inner_node = self.internal_unwrap_node(node_id);
+                    if keys.len() > 1 {
+                        ctx.path.push(key);
+                        let count = self.recursive_count(&inner_node, &keys[1..], options, ctx);
+                        ctx.path.pop();
+                        count
+                    } else if values_only {
+                        match inner_node.children.get(&EMPTY_KEY.to_string()) {
+                            Some(node_value) if node_value.is_deleted() && !return_deleted => 0,
+                            Some(_) => 1,
+                            None => 0,
+                        }
+                    } else {
+                        1
+                    }
+                }
+                node_value if node_value.is_deleted() && !return_deleted => 0,
+                _ => (keys.len() == 1) as u64,
+            };
+        }
+        count
     }
 
     pub fn recursive_keys(
@@ -253,18 +685,17 @@ impl Contract {
         node: &Node,
         keys: &[&str],
         options: &KeysOptions,
+        ctx: &mut MatchContext,
     ) {
-        let matched_entries = if keys[0] == STAR {
-            node.children.to_vec()
-        } else {
-            let key = keys[0].to_string();
-            if let Some(value) = node.children.get(&key) {
-                vec![(key, value)]
-            } else {
-                vec![]
+        let matched_entries = match_children(node, keys[0], options.limit);
+        let start = ctx.budget.enter_level();
+        for (index, (key, value)) in matched_entries.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                return;
+            }
+            if ctx.is_entry_excluded(&key, &value, keys.len() == 1) {
+                continue;
             }
-        };
-        for (key, value) in matched_entries {
             match value {
                 NodeValue::Node(node_id) => {
                     if keys.len() == 1 {
@@ -274,7 +705,7 @@ impl Contract {
                                 inner_node.children.get(&EMPTY_KEY.to_string())
                             {
                                 if options.return_deleted.unwrap_or(false)
-                                    || !matches!(node_value, NodeValue::DeletedEntry(_))
+                                    || !node_value.is_deleted()
                                 {
                                     match options.return_type.unwrap_or(KeysReturnType::True) {
                                         KeysReturnType::True => true.into(),
@@ -282,6 +713,9 @@ impl Contract {
                                             node_value.get_block_height().unwrap().into()
                                         }
                                         KeysReturnType::NodeId => node_id.into(),
+                                        KeysReturnType::Timestamp => {
+                                            timestamp_to_json(node_value.get_timestamp())
+                                        }
                                     }
                                 } else {
                                     // deleted entry
@@ -299,115 +733,203 @@ impl Contract {
                                     inner_node.block_height.into()
                                 }
                                 KeysReturnType::NodeId => node_id.into(),
+                                KeysReturnType::Timestamp => {
+                                    let inner_node = self.internal_unwrap_node(node_id);
+                                    timestamp_to_json(inner_node.timestamp)
+                                }
                             }
                         };
                         json_map_set_value(res, key, value);
                     } else {
                         let inner_node = self.internal_unwrap_node(node_id);
+                        ctx.path.push(key.clone());
                         let inner_map = json_map_get_inner_object(res, key);
-                        self.recursive_keys(inner_map, &inner_node, &keys[1..], options);
+                        self.recursive_keys(inner_map, &inner_node, &keys[1..], options, ctx);
+                        ctx.path.pop();
                     }
                 }
-                NodeValue::Value(value_at_height) => {
-                    if keys.len() == 1 {
+                node_value if node_value.is_deleted() => {
+                    if keys.len() == 1 && options.return_deleted.unwrap_or(false) {
                         let value = match options.return_type.unwrap_or(KeysReturnType::True) {
                             KeysReturnType::True => true.into(),
-                            KeysReturnType::BlockHeight => value_at_height.block_height.into(),
+                            KeysReturnType::BlockHeight => {
+                                node_value.get_block_height().unwrap().into()
+                            }
                             KeysReturnType::NodeId => Value::Null,
+                            KeysReturnType::Timestamp => {
+                                timestamp_to_json(node_value.get_timestamp())
+                            }
                         };
                         json_map_set_value(res, key, value);
                     }
                 }
-                NodeValue::DeletedEntry(block_height) => {
-                    if keys.len() == 1 && options.return_deleted.unwrap_or(false) {
+                node_value => {
+                    if keys.len() == 1 {
                         let value = match options.return_type.unwrap_or(KeysReturnType::True) {
                             KeysReturnType::True => true.into(),
-                            KeysReturnType::BlockHeight => block_height.into(),
+                            KeysReturnType::BlockHeight => {
+                                node_value.get_block_height().unwrap().into()
+                            }
                             KeysReturnType::NodeId => Value::Null,
+                            KeysReturnType::Timestamp => {
+                                timestamp_to_json(node_value.get_timestamp())
+                            }
                         };
                         json_map_set_value(res, key, value);
                     }
                 }
             }
         }
+        ctx.budget.leave_level();
     }
 
     pub fn recursive_set(
         &mut self,
         mut node: Node,
         value: &mut Value,
-        write_approved: bool,
-        writable_node_ids: &HashSet<NodeId>,
+        approval: &WriteApproval,
+        ctx: &mut SetContext,
     ) {
-        let write_approved = write_approved || writable_node_ids.contains(&node.node_id);
-        if value.is_string() || value.is_null() {
-            require!(write_approved, ERR_PERMISSION_DENIED);
-            node.set(&EMPTY_KEY.to_string(), value);
+        let approval = approval.for_node(node.node_id, &ctx.writable_nodes);
+        let author = approval.author();
+        if is_leaf_value(value) {
+            require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+            ctx.assert_leaf_allowed();
+            ctx.assert_value_within_limits(value);
+            ctx.record_change(value);
+            ctx.record_key_change(node.node_id, EMPTY_KEY);
+            node.set(
+                &EMPTY_KEY.to_string(),
+                value,
+                &author,
+                &mut ctx.blob_changes,
+            );
         } else if let Some(obj) = value.as_object_mut() {
-            for (key, value) in obj {
+            let replace = take_bool_marker(obj, KEY_REPLACE).unwrap_or(false);
+            if let Some(ordered) = take_bool_marker(obj, KEY_ORDERED) {
+                require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+                node.set_ordered(ordered);
+            }
+            if let Some(recent) = take_bool_marker(obj, KEY_RECENT) {
+                require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+                node.set_recent(recent);
+            }
+            let prev_num_children = node.children.len();
+            for (key, value) in obj.iter_mut() {
                 assert_key_valid(key.as_str());
+                ctx.path.push(key.clone());
+                ctx.assert_depth_within_limits();
                 let node_value = node.children.get(key);
                 match node_value {
                     None => {
-                        require!(write_approved, ERR_PERMISSION_DENIED);
-                        if value.is_string() || value.is_null() {
-                            node.set(key, value);
+                        require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+                        ctx.record_key_change(node.node_id, key);
+                        if is_leaf_value(value) {
+                            ctx.assert_leaf_allowed();
+                            ctx.assert_value_within_limits(value);
+                            ctx.record_change(value);
+                            node.set(key, value, &author, &mut ctx.blob_changes);
                         } else {
                             let node_id = self.create_node_id();
-                            node.children.insert(key, &NodeValue::Node(node_id));
-                            self.recursive_set(
-                                Node::new(node_id, None),
-                                value,
-                                write_approved,
-                                writable_node_ids,
-                            );
+                            node.insert_child(key, &NodeValue::Node(node_id));
+                            self.recursive_set(Node::new(node_id, None), value, &approval, ctx);
                         }
                     }
                     Some(NodeValue::Node(node_id)) => {
+                        node.touch_child(key);
                         self.recursive_set(
                             self.internal_unwrap_node(node_id),
                             value,
-                            write_approved,
-                            writable_node_ids,
+                            &approval,
+                            ctx,
                         );
                     }
                     Some(old_node_value) => {
-                        require!(write_approved, ERR_PERMISSION_DENIED);
-                        if value.is_string() || value.is_null() {
-                            node.set(key, value);
+                        require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+                        ctx.record_key_change(node.node_id, key);
+                        if is_leaf_value(value) {
+                            ctx.assert_leaf_allowed();
+                            ctx.assert_value_within_limits(value);
+                            ctx.record_change(value);
+                            node.set(key, value, &author, &mut ctx.blob_changes);
                         } else {
                             assert_ne!(
                                 key.as_str(),
                                 EMPTY_KEY,
-                                "The empty key's value should be a string or null"
+                                "The empty key's value should be a leaf value"
                             );
                             let node_id = self.create_node_id();
-                            node.children.insert(key, &NodeValue::Node(node_id));
+                            node.insert_child(key, &NodeValue::Node(node_id));
                             self.recursive_set(
                                 Node::new(node_id, Some(old_node_value)),
                                 value,
-                                write_approved,
-                                writable_node_ids,
+                                &approval,
+                                ctx,
                             );
                         }
                     }
                 }
+                ctx.path.pop();
             }
+            if replace {
+                self.internal_remove_missing_children(&mut node, obj, &approval, ctx);
+            }
+            ctx.assert_children_within_limits(&node, prev_num_children);
+            ctx.validate_node(&node, obj);
         } else {
-            env::panic_str("The JSON value is not a string, a null or an object")
+            unreachable!("Invariant: value must be a leaf value or an object")
         }
         self.internal_set_node(node);
     }
+
+    /// Replaces the children of the node that are missing in the given object with deleted
+    /// entries. The subtrees of the removed child nodes are removed from the storage.
+    fn internal_remove_missing_children(
+        &mut self,
+        node: &mut Node,
+        obj: &Map<String, Value>,
+        approval: &WriteApproval,
+        ctx: &mut SetContext,
+    ) {
+        let author = approval.author();
+        let missing_keys: Vec<String> = node
+            .children
+            .iter()
+            .filter(|(key, node_value)| !obj.contains_key(key) && !node_value.is_deleted())
+            .map(|(key, _)| key)
+            .collect();
+        for key in missing_keys {
+            require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+            ctx.record_key_change(node.node_id, &key);
+            ctx.path.push(key.clone());
+            ctx.record_change(&Value::Null);
+            ctx.path.pop();
+            match node.children.get(&key) {
+                Some(NodeValue::Node(node_id)) => {
+                    node.insert_child(&key, &NodeValue::new_deleted_entry(author.clone()));
+                    self.internal_remove_subtree(
+                        node_id,
+                        &mut ctx.blob_changes,
+                        &mut ctx.removed_node_ids,
+                    );
+                }
+                _ => node.set(&key, &Value::Null, &author, &mut ctx.blob_changes),
+            }
+        }
+    }
 }
 
-fn json_map_get_inner_object(res: &mut Map<String, Value>, key: String) -> &mut Map<String, Value> {
+pub(crate) fn json_map_get_inner_object(
+    res: &mut Map<String, Value>,
+    key: String,
+) -> &mut Map<String, Value> {
     match res.entry(key.clone()) {
         Entry::Vacant(e) => {
             e.insert(Value::Object(Map::new()));
         }
         Entry::Occupied(mut e) => {
             if !e.get().is_object() {
-                // Assuming the previous value is a string or null
+                // Assuming the previous value is a leaf value
                 let prev_value = e.insert(Value::Object(Map::new()));
                 e.get_mut()
                     .as_object_mut()
@@ -419,7 +941,7 @@ fn json_map_get_inner_object(res: &mut Map<String, Value>, key: String) -> &mut
     res.get_mut(&key).unwrap().as_object_mut().unwrap()
 }
 
-fn json_map_set_value(res: &mut Map<String, Value>, key: String, value: Value) {
+pub(crate) fn json_map_set_value(res: &mut Map<String, Value>, key: String, value: Value) {
     match res.entry(key) {
         Entry::Vacant(e) => {
             e.insert(value);
@@ -435,7 +957,26 @@ fn json_map_set_value(res: &mut Map<String, Value>, key: String, value: Value) {
     };
 }
 
-fn json_map_set_key(
+/// Returns the JSON value of a resolved leaf value, or `None` if it should be skipped.
+pub(crate) fn node_value_to_json(node_value: NodeValue, options: &GetOptions) -> Option<Value> {
+    match node_value {
+        NodeValue::Value(value_at_height) => Some(Value::String(value_at_height.value)),
+        NodeValue::TypedValue(value_at_height) => Some(value_at_height.value.to_json()),
+        NodeValue::DeletedEntry(_) | NodeValue::DeletedEntryV1(_)
+            if options.return_deleted == Some(true) =>
+        {
+            Some(Value::Null)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the timestamp as a decimal string, or `null` if it's unknown.
+pub(crate) fn timestamp_to_json(timestamp: Option<Timestamp>) -> Value {
+    timestamp.map_or(Value::Null, |timestamp| timestamp.to_string().into())
+}
+
+pub(crate) fn json_map_set_key(
     res: &mut Map<String, Value>,
     key: String,
     node_value: NodeValue,
@@ -444,18 +985,38 @@ fn json_map_set_key(
     match res.entry(key) {
         Entry::Vacant(e) => {
             let block_height = node_value.get_block_height();
-            let new_value = if let NodeValue::Value(value_at_height) = node_value {
-                Value::String(value_at_height.value)
-            } else if options.return_deleted == Some(true)
-                && matches!(node_value, NodeValue::DeletedEntry(_))
-            {
-                Value::Null
-            } else {
-                return;
+            let timestamp = node_value.get_timestamp();
+            let author = node_value.get_author().cloned();
+            let size = node_value.stored_size();
+            let new_value = match node_value_to_json(node_value, options) {
+                Some(value) => value,
+                None => return,
             };
-            if options.with_block_height == Some(true) {
+            if options.with_block_height == Some(true)
+                || options.with_timestamp == Some(true)
+                || options.with_author == Some(true)
+                || options.with_size == Some(true)
+            {
                 let mut m = Map::new();
-                m.insert(KEY_BLOCK_HEIGHT.to_string(), block_height.unwrap().into());
+                if options.with_block_height == Some(true) {
+                    m.insert(KEY_BLOCK_HEIGHT.to_string(), block_height.unwrap().into());
+                }
+                if options.with_timestamp == Some(true) {
+                    if let Some(timestamp) = timestamp {
+                        m.insert(KEY_TIMESTAMP.to_string(), timestamp.to_string().into());
+                    }
+                }
+                if options.with_author == Some(true) {
+                    if let Some(author) = author {
+                        m.insert(
+                            KEY_AUTHOR.to_string(),
+                            near_sdk::serde_json::to_value(author).unwrap(),
+                        );
+                    }
+                }
+                if options.with_size == Some(true) {
+                    m.insert(KEY_SIZE.to_string(), size.into());
+                }
                 m.insert(EMPTY_KEY.to_string(), new_value);
 
                 e.insert(Value::Object(m));
@@ -476,7 +1037,16 @@ fn json_map_set_key(
     };
 }
 
-// Returns true if the given map is not empty.
+/// Removes the marker from the object and returns its value.
+fn take_bool_marker(obj: &mut Map<String, Value>, key: &str) -> Option<bool> {
+    match obj.remove(key) {
+        None => None,
+        Some(Value::Bool(value)) => Some(value),
+        Some(_) => env::panic_str(&format!("The value of {} should be a boolean", key)),
+    }
+}
+
+// Returns true if the given map is not empty. Truncated nodes are never empty.
 fn json_map_recursive_cleanup(res: &mut Map<String, Value>) -> bool {
     let mut num_special_keys = 0;
     res.retain(|k, v| {
@@ -489,27 +1059,291 @@ fn json_map_recursive_cleanup(res: &mut Map<String, Value>) -> bool {
             _ => true,
         }
     });
-    res.len() > num_special_keys
+    res.len() > num_special_keys || res.contains_key(KEY_TRUNCATED)
 }
 
-pub(crate) fn is_key_valid(key: &str) -> bool {
-    if key.len() > MAX_KEY_LENGTH {
-        return false;
+/// Returns true if the given JSON value is stored as a single value rather than as a node.
+pub(crate) fn is_leaf_value(value: &Value) -> bool {
+    !value.is_object() || is_binary_value(value)
+}
+
+/// Returns true if the given JSON value is a binary value, e.g. `{":base64": "aGVsbG8="}`.
+pub(crate) fn is_binary_value(value: &Value) -> bool {
+    match value.as_object() {
+        Some(o) => o.len() == 1 && o.get(KEY_BASE64).map(|v| v.is_string()).unwrap_or(false),
+        None => false,
     }
-    for &c in key.as_bytes() {
+}
+
+fn is_unreserved_byte(c: u8) -> bool {
+    matches!(c, b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'.' | b'-')
+}
+
+/// Characters that are invisible or change the text direction. They can't be used in keys, because
+/// they allow creating keys that look the same as other keys.
+fn is_forbidden_char(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00AD}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{2066}'..='\u{2069}'
+                | '\u{FEFF}'
+        )
+}
+
+/// Escapes the given string into the canonical key form. The unreserved characters
+/// `[A-Za-z0-9_.-]` are kept as is, every other byte of the UTF-8 encoding is replaced with `%XX`
+/// using uppercase hex digits. E.g. `#` becomes `%23`, `é` becomes `%C3%A9` and the separator `/`
+/// becomes `%2F`.
+pub fn escape_key(s: &str) -> String {
+    let mut key = String::with_capacity(s.len());
+    for &c in s.as_bytes() {
+        if is_unreserved_byte(c) {
+            key.push(c as char);
+        } else {
+            key.push_str(&format!("%{:02X}", c));
+        }
+    }
+    key
+}
+
+/// Decodes the `%XX` sequences of the given key into bytes. Other bytes are kept as is.
+fn decode_key_bytes(key: &str) -> Vec<u8> {
+    let bytes = key.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            res.push(u8::from_str_radix(&key[i + 1..i + 3], 16).unwrap());
+            i += 3;
+            continue;
+        }
+        res.push(bytes[i]);
+        i += 1;
+    }
+    res
+}
+
+/// Returns the canonical form of the given key, or the key itself if it can't be decoded into a
+/// valid UTF-8 string. The decoded string is normalized to Unicode NFC before escaping. It allows
+/// to match keys with lowercase hex digits, unnecessary escapes, unescaped or decomposed Unicode
+/// characters in the `get` and `keys` patterns.
+pub(crate) fn canonicalize_key(key: &str) -> String {
+    match String::from_utf8(decode_key_bytes(key)) {
+        Ok(s) => escape_key(&s.nfc().collect::<String>()),
+        Err(_) => key.to_string(),
+    }
+}
+
+/// Splits the key pattern into the canonical keys. A trailing empty key is dropped, so
+/// `alex.near/profile/` matches the same value as `alex.near/profile`. Returns `None` for empty
+/// patterns.
+pub(crate) fn parse_pattern(key: &str) -> Option<Vec<String>> {
+    let mut path: Vec<&str> = key.split(SEPARATOR).collect();
+    if path.last() == Some(&EMPTY_KEY) {
+        path.pop();
+        if path.last() == Some(&EMPTY_KEY) {
+            return None;
+        }
+    }
+    if path.is_empty() {
+        return None;
+    }
+    Some(canonicalize_pattern(&path))
+}
+
+/// Splits the key pattern at the first join segment `{...}` into the prefix, the inner pattern and
+/// the suffix. The join segment should be a whole key of the pattern.
+fn split_join(key: &str) -> Option<(&str, &str, &str)> {
+    let start = key.find(JOIN_START)?;
+    let mut depth = 0;
+    let end = key[start..].find(|c| {
         match c {
-            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => {}
-            b'_' | b'.' | b'-' => {}
-            _ => return false,
+            JOIN_START => depth += 1,
+            JOIN_END => depth -= 1,
+            _ => {}
+        };
+        depth == 0
+    });
+    let end = start + end.expect("The join pattern is not closed");
+    let (prefix, suffix) = (&key[..start], &key[end + 1..]);
+    require!(
+        (prefix.is_empty() || prefix.ends_with(SEPARATOR))
+            && (suffix.is_empty() || suffix.starts_with(SEPARATOR)),
+        "The join pattern should be a whole key of the pattern"
+    );
+    Some((prefix, &key[start + 1..end], suffix))
+}
+
+/// Parses the key patterns, skipping the empty ones.
+pub(crate) fn parse_patterns(keys: &[String]) -> Vec<Vec<String>> {
+    keys.iter().filter_map(|key| parse_pattern(key)).collect()
+}
+
+/// Parses the range key pattern, e.g. `[2024-01..2024-02]`, into the bounds. The lower bound is
+/// included and the upper bound is excluded. Either bound can be omitted, e.g. `[2024-01..]`.
+pub(crate) fn parse_range(key: &str) -> Option<(Bound<String>, Bound<String>)> {
+    let range = key.strip_prefix('[')?.strip_suffix(']')?;
+    let (from, to) = range.split_once(RANGE_SEPARATOR)?;
+    let from = if from.is_empty() {
+        Bound::Unbounded
+    } else {
+        Bound::Included(from.to_string())
+    };
+    let to = if to.is_empty() {
+        Bound::Unbounded
+    } else {
+        Bound::Excluded(to.to_string())
+    };
+    Some((from, to))
+}
+
+/// Returns the children in the given range in the lexicographic order of their keys. Uses the
+/// ordered keys of the node if it has them, otherwise sorts all the keys of the node. Deleted
+/// entries are returned, but they are not counted towards the limit.
+fn match_range(
+    node: &Node,
+    (from, to): (Bound<String>, Bound<String>),
+    limit: usize,
+) -> Vec<(String, NodeValue)> {
+    if let (Bound::Included(from), Bound::Excluded(to)) = (&from, &to) {
+        if from >= to {
+            return vec![];
+        }
+    }
+    match &node.ordered_keys {
+        Some(ordered_keys) => take_live_entries(
+            ordered_keys.range((from, to)).map(|(key, _)| {
+                let value = node.children.get(&key).unwrap();
+                (key, value)
+            }),
+            limit,
+        ),
+        None => {
+            let mut entries: Vec<(String, NodeValue)> = node
+                .children
+                .iter()
+                .filter(|(key, _)| (from.as_ref(), to.as_ref()).contains(key))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            take_live_entries(entries.into_iter(), limit)
+        }
+    }
+}
+
+/// Takes the entries until `limit` entries that are not deleted are taken.
+fn take_live_entries(
+    entries: impl Iterator<Item = (String, NodeValue)>,
+    limit: usize,
+) -> Vec<(String, NodeValue)> {
+    let mut num_live_entries = 0;
+    entries
+        .take_while(|(_, value)| {
+            let is_limit_reached = num_live_entries == limit;
+            if !value.is_deleted() {
+                num_live_entries += 1;
+            }
+            !is_limit_reached
+        })
+        .collect()
+}
+
+/// Returns true if the key matches the given key of a pattern.
+pub(crate) fn key_matches(pattern_key: &str, key: &str) -> bool {
+    if pattern_key == STAR || pattern_key == RECURSIVE_STAR {
+        true
+    } else if let Some(range) = parse_range(pattern_key) {
+        range.contains(&key.to_string())
+    } else {
+        pattern_key == key
+    }
+}
+
+/// Returns true if the value at the given path is matched by the pattern. The value of a node
+/// itself is at the path of the node. A pattern ending with `**` matches the whole subtree.
+pub(crate) fn path_matches(pattern: &[String], path: &[String]) -> bool {
+    let (last_key, prefix) = pattern.split_last().unwrap();
+    let matches_prefix = |prefix: &[String]| {
+        prefix
+            .iter()
+            .zip(path)
+            .all(|(pattern_key, key)| key_matches(pattern_key, key))
+    };
+    if last_key == RECURSIVE_STAR && prefix.len() <= path.len() && matches_prefix(prefix) {
+        return true;
+    }
+    pattern.len() == path.len() && matches_prefix(pattern)
+}
+
+/// Returns the children of the node that match the given key of a pattern. The `range_limit` is
+/// the maximum number of children matched by a range.
+pub(crate) fn match_children(
+    node: &Node,
+    key: &str,
+    range_limit: Option<u32>,
+) -> Vec<(String, NodeValue)> {
+    if key == STAR {
+        node.children.to_vec()
+    } else if let Some(range) = parse_range(key) {
+        let limit = range_limit.map_or(usize::MAX, |limit| limit as usize);
+        match_range(node, range, limit)
+    } else {
+        let key = key.to_string();
+        if let Some(value) = node.children.get(&key) {
+            vec![(key, value)]
+        } else {
+            vec![]
+        }
+    }
+}
+
+fn canonicalize_pattern(path: &[&str]) -> Vec<String> {
+    path.iter()
+        .map(|&key| {
+            if key == STAR || key == RECURSIVE_STAR {
+                key.to_string()
+            } else if let Some((from, to)) = parse_range(key) {
+                let bound = |bound: Bound<String>| match bound {
+                    Bound::Included(key) | Bound::Excluded(key) => canonicalize_key(&key),
+                    Bound::Unbounded => String::new(),
+                };
+                format!("[{}{}{}]", bound(from), RANGE_SEPARATOR, bound(to))
+            } else {
+                canonicalize_key(key)
+            }
+        })
+        .collect()
+}
+
+/// Returns true if the key is in the canonical form, it decodes into a valid UTF-8 string in
+/// Unicode NFC without invisible characters and the decoded length is at most `MAX_KEY_LENGTH`
+/// bytes.
+pub(crate) fn is_key_valid(key: &str) -> bool {
+    if key.bytes().all(is_unreserved_byte) {
+        return key.len() <= MAX_KEY_LENGTH;
+    }
+    match String::from_utf8(decode_key_bytes(key)) {
+        Ok(s) => {
+            s.len() <= MAX_KEY_LENGTH
+                && !s.chars().any(is_forbidden_char)
+                && is_nfc(&s)
+                && escape_key(&s) == key
         }
+        Err(_) => false,
     }
-    true
 }
 
 pub(crate) fn assert_key_valid(key: &str) {
     assert!(
         is_key_valid(key),
-        "Key contains invalid character or longer than {}",
+        "Key contains invalid character, is not in the canonical escaped form or longer than {} bytes",
         MAX_KEY_LENGTH
     );
 }
@@ -0,0 +1,231 @@
+use crate::*;
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::StorageUsage;
+
+/// Leaf values with the serialized size of at least this number of bytes are stored once in the
+/// content-addressed blob store and shared between all nodes that store the same value.
+pub const MIN_SHARED_VALUE_LENGTH: usize = 1024;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VBlob {
+    Current(Blob),
+}
+
+impl From<VBlob> for Blob {
+    fn from(v: VBlob) -> Self {
+        match v {
+            VBlob::Current(c) => c,
+        }
+    }
+}
+
+impl From<Blob> for VBlob {
+    fn from(c: Blob) -> Self {
+        VBlob::Current(c)
+    }
+}
+
+/// The reference counts of the blobs are stored separately in `blob_refs` and `blob_ref_counts`,
+/// so changing them doesn't rewrite the value.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Blob {
+    pub value: TypedValue,
+    /// The account that covers the storage of the blob and its total reference count. It's the
+    /// account that first wrote the value, until another referrer takes it over after the owner
+    /// removed all its references.
+    pub owner_id: AccountId,
+}
+
+/// A blob with its reference counts by account, for the genesis initialization.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GenesisBlob {
+    pub hash: Base58CryptoHash,
+    pub value: TypedValue,
+    pub owner_id: AccountId,
+    pub ref_counts: Vec<(AccountId, u32)>,
+}
+
+/// Blob references that were added or removed by a single write. They are applied after the
+/// writer's storage is accounted, so the blob storage can be charged to the blob owner.
+#[derive(Default)]
+pub struct BlobChanges {
+    pub added: Vec<(CryptoHash, TypedValue)>,
+    pub removed: Vec<CryptoHash>,
+}
+
+impl Contract {
+    pub fn internal_get_blob(&self, hash: &CryptoHash) -> Option<Blob> {
+        self.blobs.get(hash).map(|b| b.into())
+    }
+
+    pub fn internal_unwrap_blob(&self, hash: &CryptoHash) -> Blob {
+        self.internal_get_blob(hash).expect("Blob is missing")
+    }
+
+    fn internal_get_blob_ref_count(&self, hash: &CryptoHash, account_id: &AccountId) -> u32 {
+        self.blob_refs
+            .get(&(*hash, account_id.clone()))
+            .unwrap_or(0)
+    }
+
+    /// Adds the new blob references first, so a value that is removed and added back within the
+    /// same write doesn't get deleted. The first writer of the blob becomes its owner. Every
+    /// referrer covers the storage of its own reference count.
+    pub fn internal_apply_blob_changes(&mut self, account_id: &str, blob_changes: BlobChanges) {
+        let account_id: AccountId = account_id.parse().expect("key is valid account id");
+        for (hash, value) in blob_changes.added {
+            let mut storage_tracker = StorageTracker::default();
+            storage_tracker.start();
+            let owner_id = match self.internal_get_blob(&hash) {
+                Some(blob) => {
+                    let ref_count = self.blob_ref_counts.get(&hash).unwrap();
+                    self.blob_ref_counts.insert(&hash, &(ref_count + 1));
+                    blob.owner_id
+                }
+                None => {
+                    let blob = Blob {
+                        value,
+                        owner_id: account_id.clone(),
+                    };
+                    self.blobs.insert(&hash, &blob.into());
+                    self.blob_ref_counts.insert(&hash, &1);
+                    account_id.clone()
+                }
+            };
+            let key = (hash, account_id.clone());
+            let account_ref_count = self.blob_refs.get(&key).unwrap_or(0);
+            self.blob_refs.insert(&key, &(account_ref_count + 1));
+            storage_tracker.stop();
+            self.internal_charge_blob_storage(&account_id, storage_tracker);
+            self.internal_take_over_blob(&hash, &owner_id, &account_id);
+        }
+        for hash in blob_changes.removed {
+            let mut storage_tracker = StorageTracker::default();
+            storage_tracker.start();
+            let key = (hash, account_id.clone());
+            let account_ref_count = self.blob_refs.get(&key).expect("Blob reference is missing");
+            if account_ref_count > 1 {
+                self.blob_refs.insert(&key, &(account_ref_count - 1));
+            } else {
+                self.blob_refs.remove(&key);
+            }
+            storage_tracker.stop();
+            self.internal_charge_blob_storage(&account_id, storage_tracker);
+
+            let ref_count = self.blob_ref_counts.get(&hash).unwrap();
+            if ref_count > 1 {
+                self.blob_ref_counts.insert(&hash, &(ref_count - 1));
+                if account_ref_count > 1 {
+                    let owner_id = self.internal_unwrap_blob(&hash).owner_id;
+                    self.internal_take_over_blob(&hash, &owner_id, &account_id);
+                }
+            } else {
+                let blob = self.internal_unwrap_blob(&hash);
+                let mut storage_tracker = StorageTracker::default();
+                storage_tracker.start();
+                self.blobs.remove(&hash);
+                self.blob_ref_counts.remove(&hash);
+                storage_tracker.stop();
+                self.internal_charge_blob_storage(&blob.owner_id, storage_tracker);
+            }
+        }
+    }
+
+    /// Moves the blob storage to the referrer, if the owner has removed all its references and the
+    /// storage balance of the referrer covers the blob. Otherwise the owner keeps covering it.
+    fn internal_take_over_blob(
+        &mut self,
+        hash: &CryptoHash,
+        owner_id: &AccountId,
+        account_id: &AccountId,
+    ) {
+        if owner_id == account_id || self.internal_get_blob_ref_count(hash, owner_id) > 0 {
+            return;
+        }
+        let mut blob = self.internal_unwrap_blob(hash);
+        let prev_owner_id = blob.owner_id.clone();
+        let ref_count = self.blob_ref_counts.get(hash).unwrap();
+        let mut storage_tracker = StorageTracker::default();
+        storage_tracker.start();
+        self.blobs.remove(hash);
+        self.blob_ref_counts.remove(hash);
+        storage_tracker.stop();
+        let owner_bytes = storage_tracker.bytes_released;
+        self.internal_charge_blob_storage(&prev_owner_id, storage_tracker);
+
+        if self.internal_can_cover_blob(account_id, owner_bytes) {
+            blob.owner_id = account_id.clone();
+        }
+        let owner_id = blob.owner_id.clone();
+        let mut storage_tracker = StorageTracker::default();
+        storage_tracker.start();
+        self.blobs.insert(hash, &blob.into());
+        self.blob_ref_counts.insert(hash, &ref_count);
+        storage_tracker.stop();
+        self.internal_charge_blob_storage(&owner_id, storage_tracker);
+    }
+
+    /// Returns true if the storage balance of the account covers the given number of extra bytes.
+    fn internal_can_cover_blob(&self, account_id: &AccountId, bytes: StorageUsage) -> bool {
+        let mut account = self.internal_unwrap_account(account_id.as_str());
+        account.used_bytes += bytes;
+        account.is_storage_covered()
+    }
+
+    /// Moves the blob storage changes to the account.
+    fn internal_charge_blob_storage(
+        &mut self,
+        account_id: &AccountId,
+        mut storage_tracker: StorageTracker,
+    ) {
+        if storage_tracker.is_empty() {
+            return;
+        }
+        let mut account = self.internal_unwrap_account(account_id.as_str());
+        account.storage_tracker.consume(&mut storage_tracker);
+        self.internal_set_account(account);
+    }
+
+    /// Replaces a shared value with the value stored in the blob.
+    pub fn internal_resolve_node_value(&self, node_value: NodeValue) -> NodeValue {
+        match node_value {
+            NodeValue::SharedValue(SharedValueAtHeight {
+                hash,
+                block_height,
+                timestamp,
+                author,
+            }) => NodeValue::TypedValue(TypedValueAtHeight {
+                value: self.internal_unwrap_blob(&hash.into()).value,
+                block_height,
+                timestamp,
+                author,
+            }),
+            node_value => node_value,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// When `decompress` is `true`, a compressed string value is returned as a regular string
+    /// value. Otherwise it's returned as stored.
+    pub fn get_blob(&self, hash: Base58CryptoHash, decompress: Option<bool>) -> Option<Blob> {
+        let mut blob = self.internal_get_blob(&hash.into())?;
+        if decompress.unwrap_or(false) {
+            blob.value = blob.value.into_decompressed();
+        }
+        Some(blob)
+    }
+
+    /// Returns the number of the references of the blob by the given account, or by all accounts
+    /// if `account_id` is not given.
+    pub fn get_blob_ref_count(&self, hash: Base58CryptoHash, account_id: Option<AccountId>) -> u32 {
+        let hash: CryptoHash = hash.into();
+        match account_id {
+            Some(account_id) => self.internal_get_blob_ref_count(&hash, &account_id),
+            None => self.blob_ref_counts.get(&hash).unwrap_or(0),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use crate::*;
+use near_sdk::json_types::U64;
+use near_sdk::serde_json::{self, Value};
+use std::collections::HashSet;
+
+/// The prefix of the event logs, see NEP-297.
+pub const EVENT_JSON_PREFIX: &str = "EVENT_JSON:";
+pub const EVENT_STANDARD: &str = "social";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+/// The maximum total length of the `set` and the `delete` event logs of a call, including the
+/// prefix and the JSON framing. Longer paths are compacted to the paths of their ancestors. The
+/// runtime limits the total length of the logs of a receipt to 16 KiB, and the rest is left for the
+/// other events of the call, e.g. the storage deposits.
+pub const MAX_PATHS_EVENT_LOGS_LENGTH: usize = 12 * 1024;
+
+/// The paths that were written or deleted within a single account.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PathsEventData {
+    pub account_id: String,
+    /// The full paths starting with the account ID. The value of a node itself is reported at the
+    /// path of the node.
+    pub paths: Vec<String>,
+    /// Set if some paths were replaced with the paths of their ancestors, meaning that anything
+    /// within these subtrees might have changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    pub block_height: U64,
+}
+
+impl PathsEventData {
+    pub fn new(account_id: &str, paths: Vec<String>) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            paths,
+            truncated: None,
+            block_height: U64(env::block_height()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GrantWritePermissionEventData {
+    pub account_id: AccountId,
+    pub permission_key: PermissionKey,
+    pub keys: Vec<String>,
+    /// Set if some keys were replaced with the keys of their ancestors to fit the log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    pub block_height: U64,
+}
+
+impl GrantWritePermissionEventData {
+    /// Logs the `grant_write_permission` event. The keys are compacted to the keys of their
+    /// ancestors at the decreasing depth, until the log fits in `MAX_PATHS_EVENT_LOGS_LENGTH`, the
+    /// same way as the paths of the `set` and the `delete` events. All keys start with the account
+    /// ID, so the log fits when only the account ID is left.
+    pub fn emit(mut self) {
+        let mut depth = max_keys_depth(&self.keys);
+        while depth > 1 && self.log_length() > MAX_PATHS_EVENT_LOGS_LENGTH {
+            depth -= 1;
+            if truncate_keys(&mut self.keys, depth) {
+                self.truncated = Some(true);
+            }
+        }
+        Event::GrantWritePermission(vec![self]).emit();
+    }
+
+    fn log_length(&self) -> usize {
+        // The empty data is serialized as `[]`.
+        Event::GrantWritePermission(vec![]).to_log().len() - 2
+            + serde_json::to_string(&[self]).unwrap().len()
+    }
+}
+
+/// The change of the storage balance of an account or a shared storage pool.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageEventData {
+    pub account_id: String,
+    pub amount: U128,
+    pub block_height: U64,
+}
+
+impl StorageEventData {
+    pub fn new(account_id: &str, amount: Balance) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            amount: U128(amount),
+            block_height: U64(env::block_height()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ShareStorageEventData {
+    pub pool_id: AccountId,
+    pub account_id: AccountId,
+    pub max_bytes: U64,
+    pub block_height: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    Set(Vec<PathsEventData>),
+    Delete(Vec<PathsEventData>),
+    GrantWritePermission(Vec<GrantWritePermissionEventData>),
+    StorageDeposit(Vec<StorageEventData>),
+    StorageWithdraw(Vec<StorageEventData>),
+    SharedStoragePoolDeposit(Vec<StorageEventData>),
+    ShareStorage(Vec<ShareStorageEventData>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+impl Event {
+    /// Logs the event in the NEP-297 format. Path events without paths are not logged.
+    pub fn emit(mut self) {
+        if let Event::Set(data) | Event::Delete(data) = &mut self {
+            data.retain(|d| !d.paths.is_empty());
+            if data.is_empty() {
+                return;
+            }
+        }
+        env::log_str(&self.to_log());
+    }
+
+    fn to_log(&self) -> String {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self,
+        };
+        format!(
+            "{}{}",
+            EVENT_JSON_PREFIX,
+            serde_json::to_string(&log).unwrap()
+        )
+    }
+}
+
+/// Returns the length of the log of the path event with the given data, or 0 if it's not logged.
+fn paths_event_log_length(
+    event: fn(Vec<PathsEventData>) -> Event,
+    data: &[PathsEventData],
+) -> usize {
+    if data.iter().all(|d| d.paths.is_empty()) {
+        return 0;
+    }
+    // The empty data is serialized as `[]`.
+    event(vec![]).to_log().len() - 2 + serde_json::to_string(data).unwrap().len()
+}
+
+pub(crate) fn paths_length(data: &[PathsEventData]) -> usize {
+    data.iter()
+        .flat_map(|d| d.paths.iter())
+        .map(|path| path.len())
+        .sum()
+}
+
+/// Returns the maximum number of keys of the paths, including the account IDs.
+fn max_paths_depth(data: &[PathsEventData]) -> usize {
+    data.iter()
+        .map(|d| max_keys_depth(&d.paths))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Returns the maximum number of keys of the given paths.
+fn max_keys_depth(paths: &[String]) -> usize {
+    paths
+        .iter()
+        .map(|path| path.split(SEPARATOR).count())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Replaces the paths deeper than `depth` keys with the paths of their ancestors at this depth.
+fn truncate_paths(data: &mut [PathsEventData], depth: usize) {
+    for d in data.iter_mut() {
+        if truncate_keys(&mut d.paths, depth) {
+            d.truncated = Some(true);
+        }
+    }
+}
+
+/// Replaces the paths deeper than `depth` keys with the paths of their ancestors at this depth,
+/// without duplicates. Returns true if any path was replaced.
+fn truncate_keys(paths: &mut Vec<String>, depth: usize) -> bool {
+    let mut truncated = false;
+    let mut seen = HashSet::new();
+    let mut res = vec![];
+    for path in paths.iter() {
+        let keys: Vec<&str> = path.split(SEPARATOR).collect();
+        if keys.len() > depth {
+            truncated = true;
+        }
+        let path = keys[..keys.len().min(depth)].join(&SEPARATOR.to_string());
+        if seen.insert(path.clone()) {
+            res.push(path);
+        }
+    }
+    *paths = res;
+    truncated
+}
+
+/// Replaces the paths with the paths of their ancestors at the decreasing depth, until the total
+/// length fits in `max_length` or only the account IDs are left.
+pub(crate) fn compact_paths(data: &mut [PathsEventData], max_length: usize) {
+    let mut depth = max_paths_depth(data);
+    while depth > 1 && paths_length(data) > max_length {
+        depth -= 1;
+        truncate_paths(data, depth);
+    }
+}
+
+/// The paths written and deleted by a call, logged as a single `set` and a single `delete` event.
+#[derive(Default)]
+pub struct ChangedPaths {
+    pub written: Vec<PathsEventData>,
+    pub deleted: Vec<PathsEventData>,
+}
+
+impl ChangedPaths {
+    /// Logs the `set` and the `delete` events. The paths of both events are compacted to the paths
+    /// of their ancestors at the decreasing depth, until the total length of the logs fits in
+    /// `MAX_PATHS_EVENT_LOGS_LENGTH`. If even the account IDs don't fit, every event is logged
+    /// with a single truncated entry for all accounts, i.e. `*`.
+    pub fn emit(mut self) {
+        let mut depth = max_paths_depth(&self.written).max(max_paths_depth(&self.deleted));
+        while self.logs_length() > MAX_PATHS_EVENT_LOGS_LENGTH {
+            if depth <= 1 {
+                self.written = truncated_paths_marker(&self.written);
+                self.deleted = truncated_paths_marker(&self.deleted);
+                break;
+            }
+            depth -= 1;
+            truncate_paths(&mut self.written, depth);
+            truncate_paths(&mut self.deleted, depth);
+        }
+        Event::Set(self.written).emit();
+        Event::Delete(self.deleted).emit();
+    }
+
+    fn logs_length(&self) -> usize {
+        paths_event_log_length(Event::Set, &self.written)
+            + paths_event_log_length(Event::Delete, &self.deleted)
+    }
+}
+
+/// Returns the single truncated entry for all accounts, meaning that anything might have changed,
+/// or nothing if there are no paths.
+pub(crate) fn truncated_paths_marker(data: &[PathsEventData]) -> Vec<PathsEventData> {
+    if data.iter().all(|d| d.paths.is_empty()) {
+        return vec![];
+    }
+    vec![PathsEventData {
+        truncated: Some(true),
+        ..PathsEventData::new(STAR, vec![STAR.to_string()])
+    }]
+}
+
+impl SetContext {
+    /// Records the change of the value at the current path. A `null` value is a deletion.
+    pub fn record_change(&mut self, value: &Value) {
+        let path = match self.path.last() {
+            Some(key) if key == EMPTY_KEY => &self.path[..self.path.len() - 1],
+            _ => &self.path[..],
+        };
+        let path = path.join(&SEPARATOR.to_string());
+        if value.is_null() {
+            self.deleted_paths.push(path);
+        } else {
+            self.written_paths.push(path);
+        }
+    }
+}
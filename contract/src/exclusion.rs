@@ -0,0 +1,120 @@
+use crate::*;
+use near_sdk::require;
+use std::collections::HashMap;
+
+/// Exclusion patterns of a `get`, `keys` or `count` call, e.g. `alice.near/widget/**`.
+///
+/// An exclusion pattern excludes the same entries that `get` would return for it. A pattern ending
+/// with `**` excludes the whole subtree of the node, so the traversal doesn't enter it.
+#[derive(Default)]
+pub struct Exclusions {
+    patterns: Vec<Vec<String>>,
+}
+
+impl Exclusions {
+    pub fn new(patterns: Vec<Vec<String>>) -> Self {
+        for pattern in &patterns {
+            require!(
+                !pattern[..pattern.len() - 1].contains(&RECURSIVE_STAR.to_string()),
+                "'**' pattern can only be used as a suffix"
+            );
+        }
+        Self { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns true if the value at the given path is excluded. The value of a node itself is at
+    /// the path of the node.
+    pub fn excludes(&self, path: &[String]) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| path_matches(pattern, path))
+    }
+
+    /// Returns true if the node at the given path and all the entries below it are excluded.
+    pub fn excludes_subtree(&self, path: &[String]) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.last().unwrap() == RECURSIVE_STAR && path_matches(pattern, path))
+    }
+}
+
+/// The state of a traversal of the key patterns of a single `get`, `keys` or `count` call.
+#[derive(Default)]
+pub struct MatchContext {
+    /// The path of the node that is being traversed.
+    pub path: Vec<String>,
+    pub exclusions: Exclusions,
+    pub budget: GasBudget,
+    /// The sizes of the subtrees of the nodes that were computed for the `with_size` option.
+    pub subtree_sizes: HashMap<NodeId, u64>,
+}
+
+impl MatchContext {
+    pub fn new(exclusions: Exclusions, budget: GasBudget) -> Self {
+        Self {
+            path: vec![],
+            exclusions,
+            budget,
+            subtree_sizes: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the value of the child with the given key of the current node is excluded.
+    /// The empty key is the value of the current node itself.
+    pub fn is_value_excluded(&mut self, key: &str) -> bool {
+        if self.exclusions.is_empty() {
+            return false;
+        }
+        if key == EMPTY_KEY {
+            return self.exclusions.excludes(&self.path);
+        }
+        self.path.push(key.to_string());
+        let res = self.exclusions.excludes(&self.path);
+        self.path.pop();
+        res
+    }
+
+    /// Returns true if the child node with the given key of the current node is excluded with its
+    /// whole subtree.
+    pub fn is_subtree_excluded(&mut self, key: &str) -> bool {
+        if self.exclusions.is_empty() {
+            return false;
+        }
+        self.path.push(key.to_string());
+        let res = self.exclusions.excludes_subtree(&self.path);
+        self.path.pop();
+        res
+    }
+
+    /// Returns true if the matched child with the given key of the current node should be skipped.
+    /// The `is_match` is true when the child is matched by the last key of the pattern, so the
+    /// value of a child node itself is returned for it.
+    pub fn is_entry_excluded(&mut self, key: &str, value: &NodeValue, is_match: bool) -> bool {
+        match value {
+            NodeValue::Node(_) => {
+                self.is_subtree_excluded(key) || (is_match && self.is_value_excluded(key))
+            }
+            _ => self.is_value_excluded(key),
+        }
+    }
+}
+
+impl Contract {
+    /// Parses the exclusion patterns of the given option. The joins are expanded within the budget.
+    pub fn internal_get_exclusions(
+        &self,
+        exclude: &Option<Vec<String>>,
+        budget: &mut GasBudget,
+    ) -> Exclusions {
+        match exclude {
+            Some(exclude) => {
+                Exclusions::new(parse_patterns(&self.internal_expand_joins(exclude, budget)))
+            }
+            None => Exclusions::default(),
+        }
+    }
+}
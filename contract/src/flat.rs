@@ -0,0 +1,308 @@
+use crate::*;
+use near_sdk::json_types::U64;
+use near_sdk::serde_json::Value;
+use near_sdk::{require, BlockHeight, Timestamp};
+use std::collections::HashSet;
+
+/// A single matched entry returned by `get` and `keys` with the `flat` option.
+///
+/// The value of a node itself, that is stored under the empty key, is returned at the path of the
+/// node, e.g. `alex.near/widget/Main` instead of `alex.near/widget/Main/`.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PathRecord {
+    /// The full path of the entry starting with the account ID.
+    pub path: String,
+    /// The value of the entry. It's `null` for deleted entries, and it's not returned by `keys`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    /// The block height when the value or the node was last updated.
+    pub block_height: BlockHeight,
+    /// The timestamp of the block when the value or the node was last updated. It's not returned
+    /// for the entries that were last written before `0.13.0`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<U64>,
+    /// The permission key that authorized the write of the value, if it was not written by the
+    /// account itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<PermissionKey>,
+    /// The ID of the node at the path, or `null` if the path points to a leaf value.
+    pub node_id: Option<NodeId>,
+    /// Whether the node was not expanded because of the `max_depth` option.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<bool>,
+    /// The number of children of a truncated node, or of a node with the `with_child_count`
+    /// option.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<u64>,
+    /// The number of bytes of the value, or of the subtree if the path points to a node. Only
+    /// returned with the `with_size` option.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl PathRecord {
+    pub fn new(
+        path: &[String],
+        value: Option<Value>,
+        block_height: BlockHeight,
+        timestamp: Option<Timestamp>,
+        node_id: Option<NodeId>,
+    ) -> Self {
+        Self {
+            path: path.join(&SEPARATOR.to_string()),
+            value,
+            block_height,
+            timestamp: timestamp.map(U64),
+            node_id,
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts the records into the JSON array. When multiple patterns match the same path, only the
+/// first record is kept.
+pub(crate) fn records_to_json(mut records: Vec<PathRecord>) -> Value {
+    let mut paths = HashSet::new();
+    records.retain(|record| paths.insert(record.path.clone()));
+    near_sdk::serde_json::to_value(records).unwrap()
+}
+
+impl Contract {
+    /// Same as `recursive_get`, but collects the matched values into the list of records.
+    pub fn recursive_get_flat(
+        &self,
+        res: &mut Vec<PathRecord>,
+        ctx: &mut MatchContext,
+        node: &Node,
+        keys: &[&str],
+        options: &GetOptions,
+        depth: u32,
+    ) {
+        let is_recursive_match_all = keys[0] == RECURSIVE_STAR;
+        if is_recursive_match_all {
+            require!(keys.len() == 1, "'**' pattern can only be used as a suffix")
+        }
+        let matched_entries = if is_recursive_match_all {
+            node.children.to_vec()
+        } else {
+            match_children(node, keys[0], options.limit)
+        };
+        let is_match = keys.len() == 1 && !is_recursive_match_all;
+        let start = ctx.budget.enter_level();
+        for (index, (key, value)) in matched_entries.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                return;
+            }
+            if ctx.is_entry_excluded(&key, &value, is_match) {
+                continue;
+            }
+            match value {
+                NodeValue::Node(node_id) => {
+                    ctx.path.push(key);
+                    let inner_node = self.internal_unwrap_node(node_id);
+                    if keys.len() > 1 {
+                        self.recursive_get_flat(res, ctx, &inner_node, &keys[1..], options, 0);
+                    }
+                    if is_recursive_match_all {
+                        if options.max_depth.map_or(false, |d| depth + 1 >= d) {
+                            self.push_truncated_record(res, ctx, &inner_node, options);
+                        } else {
+                            self.recursive_get_flat(
+                                res,
+                                ctx,
+                                &inner_node,
+                                keys,
+                                options,
+                                depth + 1,
+                            );
+                        }
+                    } else if keys.len() == 1 {
+                        if let Some(node_value) = inner_node.children.get(&EMPTY_KEY.to_string()) {
+                            self.push_value_record(
+                                res,
+                                ctx,
+                                node_value,
+                                Some(&inner_node),
+                                options,
+                            );
+                        }
+                    }
+                    ctx.path.pop();
+                }
+                node_value => {
+                    if keys.len() == 1 {
+                        if key == EMPTY_KEY {
+                            self.push_value_record(res, ctx, node_value, Some(node), options);
+                        } else {
+                            ctx.path.push(key);
+                            self.push_value_record(res, ctx, node_value, None, options);
+                            ctx.path.pop();
+                        }
+                    }
+                }
+            }
+        }
+        ctx.budget.leave_level();
+    }
+
+    /// Adds the record of the truncated node at the current path with its own value.
+    fn push_truncated_record(
+        &self,
+        res: &mut Vec<PathRecord>,
+        ctx: &mut MatchContext,
+        node: &Node,
+        options: &GetOptions,
+    ) {
+        let value = node
+            .children
+            .get(&EMPTY_KEY.to_string())
+            .and_then(|node_value| {
+                node_value_to_json(self.internal_resolve_node_value(node_value), options)
+            });
+        let size = self.internal_get_record_size(ctx, Some(node), None, options);
+        res.push(PathRecord {
+            truncated: Some(true),
+            children: Some(node.num_children(options.return_deleted.unwrap_or(false))),
+            size,
+            ..PathRecord::new(
+                &ctx.path,
+                value,
+                node.block_height,
+                node.timestamp,
+                Some(node.node_id),
+            )
+        });
+    }
+
+    /// Adds the record of the value at the current path. The `node` is the node at the path if the
+    /// value is the value of the node itself.
+    fn push_value_record(
+        &self,
+        res: &mut Vec<PathRecord>,
+        ctx: &mut MatchContext,
+        node_value: NodeValue,
+        node: Option<&Node>,
+        options: &GetOptions,
+    ) {
+        let block_height = match node_value.get_block_height() {
+            Some(block_height) => block_height,
+            None => return,
+        };
+        let timestamp = node_value.get_timestamp();
+        let author = node_value.get_author().cloned();
+        let node_value = self.internal_resolve_node_value(node_value);
+        let size = self.internal_get_record_size(ctx, node, Some(&node_value), options);
+        if let Some(value) = node_value_to_json(node_value, options) {
+            let node_id = node.map(|node| node.node_id);
+            res.push(PathRecord {
+                children: node
+                    .filter(|_| options.with_child_count == Some(true))
+                    .map(|node| node.num_children(options.return_deleted.unwrap_or(false))),
+                size,
+                author,
+                ..PathRecord::new(&ctx.path, Some(value), block_height, timestamp, node_id)
+            });
+        }
+    }
+
+    /// Returns the size of the record with the `with_size` option. It's the size of the subtree
+    /// for the records of nodes, and the size of the value otherwise. It's `None` if the budget is
+    /// exceeded while computing the size of the subtree.
+    fn internal_get_record_size(
+        &self,
+        ctx: &mut MatchContext,
+        node: Option<&Node>,
+        node_value: Option<&NodeValue>,
+        options: &GetOptions,
+    ) -> Option<u64> {
+        if options.with_size != Some(true) {
+            return None;
+        }
+        match (node, node_value) {
+            (Some(node), _) => self.internal_get_subtree_size(node, ctx),
+            (None, Some(node_value)) => Some(node_value.stored_size()),
+            (None, None) => None,
+        }
+    }
+
+    /// Same as `recursive_keys`, but collects the matched keys into the list of records.
+    pub fn recursive_keys_flat(
+        &self,
+        res: &mut Vec<PathRecord>,
+        ctx: &mut MatchContext,
+        node: &Node,
+        keys: &[&str],
+        options: &KeysOptions,
+    ) {
+        let return_deleted = options.return_deleted.unwrap_or(false);
+        let matched_entries = match_children(node, keys[0], options.limit);
+        let start = ctx.budget.enter_level();
+        for (index, (key, value)) in matched_entries.into_iter().enumerate().skip(start) {
+            if !ctx.budget.visit(index) {
+                return;
+            }
+            if ctx.is_entry_excluded(&key, &value, keys.len() == 1) {
+                continue;
+            }
+            let (block_height, timestamp, node_id) = match value {
+                NodeValue::Node(node_id) => {
+                    let inner_node = self.internal_unwrap_node(node_id);
+                    if keys.len() > 1 {
+                        ctx.path.push(key);
+                        self.recursive_keys_flat(res, ctx, &inner_node, &keys[1..], options);
+                        ctx.path.pop();
+                        continue;
+                    }
+                    if options.values_only.unwrap_or(false) {
+                        match inner_node.children.get(&EMPTY_KEY.to_string()) {
+                            Some(node_value) if node_value.is_deleted() && !return_deleted => {
+                                continue
+                            }
+                            Some(node_value) => (
+                                node_value.get_block_height().unwrap(),
+                                node_value.get_timestamp(),
+                                Some(node_id),
+                            ),
+                            None => continue,
+                        }
+                    } else {
+                        (inner_node.block_height, inner_node.timestamp, Some(node_id))
+                    }
+                }
+                node_value if node_value.is_deleted() && !return_deleted => continue,
+                node_value => {
+                    if keys.len() > 1 {
+                        continue;
+                    }
+                    if key == EMPTY_KEY {
+                        let block_height = node_value.get_block_height().unwrap();
+                        res.push(PathRecord::new(
+                            &ctx.path,
+                            None,
+                            block_height,
+                            node_value.get_timestamp(),
+                            Some(node.node_id),
+                        ));
+                        continue;
+                    }
+                    (
+                        node_value.get_block_height().unwrap(),
+                        node_value.get_timestamp(),
+                        None,
+                    )
+                }
+            };
+            ctx.path.push(key);
+            res.push(PathRecord::new(
+                &ctx.path,
+                None,
+                block_height,
+                timestamp,
+                node_id,
+            ));
+            ctx.path.pop();
+        }
+        ctx.budget.leave_level();
+    }
+}
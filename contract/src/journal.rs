@@ -0,0 +1,216 @@
+use crate::*;
+use near_sdk::BlockHeight;
+use std::collections::BTreeMap;
+
+/// The default number of the most recent journal entries that are retained. An entry takes at most
+/// about 400 bytes with a 64 characters account ID and a 256 characters key.
+pub const DEFAULT_JOURNAL_MAX_ENTRIES: u64 = 10_000;
+/// The default number of the entries returned by `get_changes`.
+pub const DEFAULT_JOURNAL_CHANGES_LIMIT: u64 = 100;
+
+/// The number of old entries that can be pruned by a single append in addition to the number of
+/// appended entries, so the journal shrinks gradually after the retention is lowered.
+const MAX_EXTRA_PRUNED_ENTRIES: u64 = 16;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VJournalEntry {
+    Current(JournalEntry),
+}
+
+impl From<VJournalEntry> for JournalEntry {
+    fn from(v: VJournalEntry) -> Self {
+        match v {
+            VJournalEntry::Current(c) => c,
+        }
+    }
+}
+
+impl From<JournalEntry> for VJournalEntry {
+    fn from(c: JournalEntry) -> Self {
+        VJournalEntry::Current(c)
+    }
+}
+
+/// A change of the key in the node. The key of the node's own value is the empty key.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub block_height: BlockHeight,
+    pub account_id: String,
+    pub node_id: NodeId,
+    pub key: String,
+}
+
+/// The append-only journal of the changed keys. Only the last `max_entries` entries are retained.
+/// The storage of an entry is paid by the account of the changed key until the entry is pruned.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct Journal {
+    pub entries: LookupMap<u64, VJournalEntry>,
+    /// The sequence number of the oldest retained entry.
+    pub first_seq: u64,
+    /// The sequence number of the next entry.
+    pub next_seq: u64,
+    /// The maximum number of retained entries. The journal is disabled if it's `0`.
+    pub max_entries: u64,
+    /// The highest block height of the pruned entries, or of the changes that were not recorded
+    /// because the journal was disabled. The changes after it are all retained.
+    pub pruned_block_height: BlockHeight,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JournalInfo {
+    pub first_seq: u64,
+    pub next_seq: u64,
+    pub max_entries: u64,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self {
+            entries: LookupMap::new(StorageKey::Journal),
+            first_seq: 0,
+            next_seq: 0,
+            max_entries: DEFAULT_JOURNAL_MAX_ENTRIES,
+            pruned_block_height: 0,
+        }
+    }
+
+    /// Appends the changed keys of the account. When the journal is disabled, the sequence numbers
+    /// are still consumed, so the clients can detect the gap.
+    fn append(&mut self, account_id: &str, changes: Vec<(NodeId, String)>) {
+        if self.max_entries == 0 {
+            self.pruned_block_height = env::block_height();
+        }
+        for (node_id, key) in changes {
+            if self.max_entries > 0 {
+                let entry = JournalEntry {
+                    seq: self.next_seq,
+                    block_height: env::block_height(),
+                    account_id: account_id.to_string(),
+                    node_id,
+                    key,
+                };
+                self.entries.insert(&self.next_seq, &entry.into());
+            }
+            self.next_seq += 1;
+        }
+    }
+
+    /// Moves the start of the journal past the entries outside of the retention window, after
+    /// `num_appended` entries were appended. Returns the sequence numbers of the entries to remove.
+    fn prune(&mut self, num_appended: u64) -> std::ops::Range<u64> {
+        let first_seq = self.first_seq;
+        let num_pruned = (self.next_seq - self.first_seq)
+            .saturating_sub(self.max_entries)
+            .min(num_appended + MAX_EXTRA_PRUNED_ENTRIES);
+        self.first_seq += num_pruned;
+        first_seq..self.first_seq
+    }
+
+    /// Returns the sequence number of the first retained entry after the given block height, or
+    /// `next_seq` if there is none. The entries are ordered by the block height, so it's found by a
+    /// binary search. The entries that were not recorded because the journal was disabled are
+    /// treated as older ones, since they are before `pruned_block_height`.
+    pub fn first_seq_after(&self, block_height: BlockHeight) -> u64 {
+        let (mut low, mut high) = (self.first_seq, self.next_seq);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let is_after = self.entries.get(&mid).map_or(false, |entry| {
+                JournalEntry::from(entry).block_height > block_height
+            });
+            if is_after {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        low
+    }
+}
+
+impl SetContext {
+    /// Records the change of the key in the node for the journal.
+    pub fn record_key_change(&mut self, node_id: NodeId, key: &str) {
+        self.changed_keys.push((node_id, key.to_string()));
+    }
+}
+
+impl Contract {
+    /// Appends the changed keys of the account to the journal and prunes the entries outside of the
+    /// retention window. The new entries are charged to the account, and the storage of the pruned
+    /// entries is released to the accounts of their keys. Should be called after the account is
+    /// saved.
+    pub fn internal_append_journal(&mut self, account_id: &str, changes: Vec<(NodeId, String)>) {
+        if changes.is_empty() {
+            return;
+        }
+        let num_changes = changes.len() as u64;
+        let mut account = self.internal_unwrap_account(account_id);
+        account.storage_tracker.start();
+        self.journal.append(account_id, changes);
+        account.storage_tracker.stop();
+        self.internal_set_account(account);
+
+        let mut pruned_seqs: BTreeMap<String, Vec<u64>> = BTreeMap::new();
+        for seq in self.journal.prune(num_changes) {
+            if let Some(entry) = self.journal.entries.get(&seq) {
+                let entry: JournalEntry = entry.into();
+                self.journal.pruned_block_height =
+                    std::cmp::max(self.journal.pruned_block_height, entry.block_height);
+                pruned_seqs.entry(entry.account_id).or_default().push(seq);
+            }
+        }
+        for (account_id, seqs) in pruned_seqs {
+            let mut account = self.internal_get_account(&account_id);
+            if let Some(account) = account.as_mut() {
+                account.storage_tracker.start();
+            }
+            for seq in seqs {
+                self.journal.entries.remove(&seq);
+            }
+            if let Some(mut account) = account {
+                account.storage_tracker.stop();
+                self.internal_set_account(account);
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Returns the journal entries from the given sequence number up to a given limit, which is
+    /// `DEFAULT_JOURNAL_CHANGES_LIMIT` by default. If `from_seq` is older than the first retained
+    /// entry, the entries start from the first retained one, and the client should resync the
+    /// skipped changes with `get_nodes`.
+    pub fn get_changes(&self, from_seq: Option<u64>, limit: Option<u64>) -> Vec<JournalEntry> {
+        let from_seq = std::cmp::max(from_seq.unwrap_or(0), self.journal.first_seq);
+        let limit = limit.unwrap_or(DEFAULT_JOURNAL_CHANGES_LIMIT);
+        (from_seq..std::cmp::min(self.journal.next_seq, from_seq.saturating_add(limit)))
+            .filter_map(|seq| self.journal.entries.get(&seq).map(|v| v.into()))
+            .collect()
+    }
+
+    pub fn get_journal_info(&self) -> JournalInfo {
+        JournalInfo {
+            first_seq: self.journal.first_seq,
+            next_seq: self.journal.next_seq,
+            max_entries: self.journal.max_entries,
+        }
+    }
+
+    /// Sets the number of the retained journal entries. `0` disables the journal. The entries
+    /// outside of the new window are pruned gradually by the following writes, which releases their
+    /// storage to the accounts that paid for them.
+    #[private]
+    pub fn set_journal_max_entries(&mut self, max_entries: u64) {
+        self.journal.max_entries = max_entries;
+    }
+}
@@ -1,4 +1,5 @@
 use crate::*;
+use near_sdk::BlockHeight;
 
 /// Legacy version of the account, before shared storage pools were introduced.
 #[derive(BorshSerialize, BorshDeserialize, Serialize)]
@@ -26,3 +27,64 @@ impl From<AccountV0> for Account {
         }
     }
 }
+
+/// Legacy version of the node from `0.12.0`, before the number of deleted children was tracked.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct NodeV0 {
+    #[borsh_skip]
+    pub node_id: NodeId,
+    pub block_height: BlockHeight,
+    pub children: UnorderedMap<String, NodeValue>,
+}
+
+/// The number of deleted children is unknown, so it's counted by the views that need it.
+impl From<NodeV0> for Node {
+    fn from(c: NodeV0) -> Self {
+        Self {
+            node_id: c.node_id,
+            block_height: c.block_height,
+            children: c.children,
+            num_deleted_children: None,
+            ordered_keys: None,
+            timestamp: None,
+            recent_keys: None,
+        }
+    }
+}
+
+/// Legacy version of the contract state from `0.12.0`.
+#[derive(BorshDeserialize)]
+pub struct ContractV0 {
+    pub accounts: LookupMap<NodeId, VAccount>,
+    pub root_node: NodeV0,
+    pub nodes: LookupMap<NodeId, VNode>,
+    pub node_count: NodeId,
+    pub status: ContractStatus,
+    pub shared_storage_pools: LookupMap<AccountId, VSharedStoragePool>,
+}
+
+impl From<ContractV0> for Contract {
+    fn from(c: ContractV0) -> Self {
+        Self {
+            accounts: c.accounts,
+            // The root node only contains account nodes, which are never deleted.
+            root_node: Node {
+                num_deleted_children: Some(0),
+                ..c.root_node.into()
+            },
+            nodes: c.nodes,
+            node_count: c.node_count,
+            status: c.status,
+            shared_storage_pools: c.shared_storage_pools,
+            blobs: LookupMap::new(StorageKey::Blobs),
+            blob_refs: LookupMap::new(StorageKey::BlobRefs),
+            blob_ref_counts: LookupMap::new(StorageKey::BlobRefCounts),
+            schemas: UnorderedMap::new(StorageKey::Schemas),
+            limits: Limits::default(),
+            journal: Journal::new(),
+            subscriptions: TreeMap::new(StorageKey::Subscriptions),
+            subscription_prefixes: LookupMap::new(StorageKey::SubscriptionPrefixes),
+            notification_queue: NotificationQueue::new(),
+        }
+    }
+}
@@ -1,29 +1,50 @@
 mod account;
 mod api;
+mod blob;
+mod events;
+mod exclusion;
+mod flat;
+mod journal;
 mod node;
+mod partial;
 mod permission;
+mod recent;
+mod schema;
 mod storage_tracker;
 mod upgrade;
 mod utils;
 mod legacy;
+mod limits;
 mod shared_storage;
+mod subscription;
+mod subtree;
 
 pub use crate::account::*;
 pub use crate::api::*;
+pub use crate::blob::*;
+pub use crate::events::*;
+pub use crate::exclusion::*;
+pub use crate::flat::*;
+pub use crate::journal::*;
+pub use crate::limits::*;
 pub use crate::node::*;
+pub use crate::partial::*;
 pub use crate::permission::*;
+pub use crate::recent::*;
+pub use crate::schema::*;
 pub use crate::shared_storage::*;
+pub use crate::subscription::*;
 use crate::storage_tracker::*;
 use crate::utils::*;
 use crate::legacy::*;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::collections::{LookupMap, TreeMap, UnorderedMap};
 use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
     assert_one_yocto, env, near_bindgen, require, AccountId, Balance, BorshStorageKey,
-    PanicOnDefault, Promise, StorageUsage,
+    CryptoHash, PanicOnDefault, Promise, StorageUsage,
 };
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -34,6 +55,17 @@ enum StorageKey {
     Node { node_id: NodeId },
     Permissions { node_id: NodeId },
     SharedStoragePools,
+    Blobs,
+    Schemas,
+    OrderedChildren { node_id: NodeId },
+    Journal,
+    Subscriptions,
+    RecentChildren { node_id: NodeId },
+    RecentChildHeights { node_id: NodeId },
+    BlobRefs,
+    BlobRefCounts,
+    SubscriptionPrefixes,
+    NotificationQueue,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Copy, Clone)]
@@ -55,6 +87,21 @@ pub struct Contract {
     pub node_count: NodeId,
     pub status: ContractStatus,
     pub shared_storage_pools: LookupMap<AccountId, VSharedStoragePool>,
+    pub blobs: LookupMap<CryptoHash, VBlob>,
+    /// The number of the references of a blob by an account.
+    pub blob_refs: LookupMap<(CryptoHash, AccountId), u32>,
+    /// The total number of the references of a blob.
+    pub blob_ref_counts: LookupMap<CryptoHash, u32>,
+    /// Schemas keyed by the first segment of their patterns, e.g. an account ID or `*`.
+    pub schemas: UnorderedMap<String, VSchemas>,
+    pub limits: Limits,
+    pub journal: Journal,
+    /// Subscriptions keyed by the literal prefix of their patterns, the subscriber ID and the
+    /// pattern.
+    pub subscriptions: TreeMap<SubscriptionKey, VSubscription>,
+    /// The number of the subscriptions by the literal prefix of their patterns.
+    pub subscription_prefixes: LookupMap<String, u32>,
+    pub notification_queue: NotificationQueue,
 }
 
 #[near_bindgen]
@@ -68,6 +115,15 @@ impl Contract {
             node_count: 1,
             status: ContractStatus::Genesis,
             shared_storage_pools: LookupMap::new(StorageKey::SharedStoragePools),
+            blobs: LookupMap::new(StorageKey::Blobs),
+            blob_refs: LookupMap::new(StorageKey::BlobRefs),
+            blob_ref_counts: LookupMap::new(StorageKey::BlobRefCounts),
+            schemas: UnorderedMap::new(StorageKey::Schemas),
+            limits: Limits::default(),
+            journal: Journal::new(),
+            subscriptions: TreeMap::new(StorageKey::Subscriptions),
+            subscription_prefixes: LookupMap::new(StorageKey::SubscriptionPrefixes),
+            notification_queue: NotificationQueue::new(),
         }
     }
 
@@ -0,0 +1,136 @@
+use crate::*;
+use near_sdk::serde_json::Value;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Limits {
+    /// The maximum length of a leaf value. The length of binary values is the number of decoded
+    /// bytes, and the length of other non-string values is the length of their JSON
+    /// representation.
+    pub max_value_length: u32,
+    /// The maximum number of keys in a path, including the account ID.
+    pub max_depth: u32,
+    /// The maximum number of children of a node. Nodes that already exceed it can't get new keys.
+    pub max_children: u32,
+    /// The maximum number of keys in the data of a single `set` call.
+    pub max_keys_per_call: u32,
+}
+
+impl Limits {
+    fn assert_valid(&self) {
+        require!(
+            self.max_value_length > 0 && self.max_children > 0 && self.max_keys_per_call > 0,
+            "The limits should be greater than 0"
+        );
+        // A path consists of at least the account ID and a key.
+        require!(
+            self.max_depth >= 2,
+            "The maximum depth should be at least 2"
+        );
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_value_length: 512 * 1024,
+            max_depth: 32,
+            max_children: 10_000,
+            max_keys_per_call: 1_000,
+        }
+    }
+}
+
+impl Limits {
+    /// Asserts that a path with the given number of keys, including the account ID, doesn't
+    /// exceed the maximum depth.
+    pub fn assert_depth(&self, depth: usize) {
+        require!(
+            depth <= self.max_depth as usize,
+            format!("The path exceeds the maximum depth {}", self.max_depth)
+        );
+    }
+
+    /// Asserts that the node doesn't exceed the maximum number of children, unless it had at least
+    /// as many children before the write.
+    pub fn assert_children(&self, node: &Node, prev_num_children: u64) {
+        let num_children = node.children.len();
+        require!(
+            num_children <= prev_num_children || num_children <= self.max_children as u64,
+            format!(
+                "The node exceeds the maximum number of children {}",
+                self.max_children
+            )
+        );
+    }
+}
+
+impl SetContext {
+    pub fn assert_depth_within_limits(&self) {
+        self.limits.assert_depth(self.path.len());
+    }
+
+    pub fn assert_value_within_limits(&self, value: &Value) {
+        let length = if is_binary_value(value) {
+            decoded_base64_length(value[KEY_BASE64].as_str().unwrap_or_default())
+        } else {
+            match value.as_str() {
+                Some(s) => s.len(),
+                None => value.to_string().len(),
+            }
+        };
+        require!(
+            length <= self.limits.max_value_length as usize,
+            format!(
+                "The value exceeds the maximum length {}",
+                self.limits.max_value_length
+            )
+        );
+    }
+
+    pub fn assert_children_within_limits(&self, node: &Node, prev_num_children: u64) {
+        self.limits.assert_children(node, prev_num_children);
+    }
+}
+
+/// Returns the number of bytes encoded by the given base64 string. Invalid strings are rejected
+/// later, when the value is decoded.
+fn decoded_base64_length(s: &str) -> usize {
+    let padding = s.bytes().rev().take_while(|&b| b == b'=').count();
+    (s.len() * 3 / 4).saturating_sub(padding)
+}
+
+/// Returns the number of keys in the given data, including the keys of the nested objects.
+pub(crate) fn count_keys(value: &Value) -> usize {
+    match value.as_object() {
+        Some(obj) if !is_leaf_value(value) => {
+            obj.len() + obj.values().map(count_keys).sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+impl Contract {
+    pub fn assert_keys_within_limits(&self, data: &Value) {
+        require!(
+            count_keys(data) <= self.limits.max_keys_per_call as usize,
+            format!(
+                "The data exceeds the maximum number of keys per call {}",
+                self.limits.max_keys_per_call
+            )
+        );
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[private]
+    pub fn set_limits(&mut self, limits: Limits) {
+        limits.assert_valid();
+        self.limits = limits;
+    }
+
+    pub fn get_limits(&self) -> Limits {
+        self.limits.clone()
+    }
+}
@@ -1,9 +1,19 @@
 use crate::*;
-use near_sdk::{require, BlockHeight};
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8};
+use near_sdk::serde_json::{self, Value};
+use near_sdk::{require, BlockHeight, Timestamp};
+use std::collections::HashSet;
+use std::convert::TryInto;
 
 pub const EMPTY_KEY: &str = "";
 pub const ERR_PERMISSION_DENIED: &str = "Permission Denied";
+/// String values of at least this length are stored compressed if it reduces their size.
+pub const MIN_COMPRESSED_VALUE_LENGTH: usize = 512;
+/// The default number of the journal entries scanned by `get_nodes_since`.
+pub const DEFAULT_NODES_SINCE_LIMIT: u32 = 100;
 
+/// Legacy string value from `0.12.0`, without the timestamp. New strings are stored as typed
+/// values.
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct ValueAtHeight {
@@ -11,26 +21,205 @@ pub struct ValueAtHeight {
     pub block_height: BlockHeight,
 }
 
+/// A typed leaf value. Numbers and arrays are kept in their serialized JSON form, so they are
+/// returned exactly as they were written. Binary values are stored as raw bytes and exposed as
+/// base64 strings. Large strings are stored as LZ4 compressed bytes and returned decompressed.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TypedValue {
+    Number(String),
+    Bool(bool),
+    Array(String),
+    Binary(Base64VecU8),
+    CompressedString(Base64VecU8),
+    String(String),
+}
+
+impl TypedValue {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(s) => Some(if let Some(compressed) = compress_string(s) {
+                TypedValue::CompressedString(compressed)
+            } else {
+                TypedValue::String(s.clone())
+            }),
+            Value::Number(n) => Some(TypedValue::Number(n.to_string())),
+            Value::Bool(b) => Some(TypedValue::Bool(*b)),
+            Value::Array(_) => Some(TypedValue::Array(value.to_string())),
+            Value::Object(_) if is_binary_value(value) => {
+                let bytes = serde_json::from_value(value[KEY_BASE64].clone())
+                    .unwrap_or_else(|_| env::panic_str("The binary value is not a valid base64"));
+                Some(TypedValue::Binary(bytes))
+            }
+            _ => None,
+        }
+    }
+
+    /// Replaces a compressed string with the original string.
+    pub fn into_decompressed(self) -> Self {
+        match self {
+            TypedValue::CompressedString(bytes) => TypedValue::String(decompress_string(&bytes.0)),
+            value => value,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            TypedValue::Number(s) | TypedValue::Array(s) => {
+                serde_json::from_str(s).expect("Invariant: typed value is a valid JSON")
+            }
+            TypedValue::Bool(b) => Value::Bool(*b),
+            TypedValue::Binary(bytes) => serde_json::to_value(bytes).unwrap(),
+            TypedValue::CompressedString(bytes) => Value::String(decompress_string(&bytes.0)),
+            TypedValue::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+/// Returns the compressed bytes of the given string, if the string is large enough and the
+/// compression reduces its size.
+fn compress_string(s: &str) -> Option<Base64VecU8> {
+    if s.len() < MIN_COMPRESSED_VALUE_LENGTH {
+        return None;
+    }
+    let compressed = lz4_flex::compress_prepend_size(s.as_bytes());
+    if compressed.len() < s.len() {
+        Some(compressed.into())
+    } else {
+        None
+    }
+}
+
+fn decompress_string(bytes: &[u8]) -> String {
+    let decompressed =
+        lz4_flex::decompress_size_prepended(bytes).expect("Invariant: compressed value is valid");
+    String::from_utf8(decompressed).expect("Invariant: compressed value is a valid string")
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TypedValueAtHeight {
+    pub value: TypedValue,
+    pub block_height: BlockHeight,
+    #[serde(with = "u64_dec_format")]
+    pub timestamp: Timestamp,
+    /// The permission key that authorized the write, or `None` if it was written by the account
+    /// itself.
+    pub author: Option<PermissionKey>,
+}
+
+/// A reference to a value in the content-addressed blob store.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SharedValueAtHeight {
+    pub hash: Base58CryptoHash,
+    pub block_height: BlockHeight,
+    #[serde(with = "u64_dec_format")]
+    pub timestamp: Timestamp,
+    /// The permission key that authorized the write, or `None` if it was written by the account
+    /// itself.
+    pub author: Option<PermissionKey>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DeletedEntryAtHeight {
+    pub block_height: BlockHeight,
+    #[serde(with = "u64_dec_format")]
+    pub timestamp: Timestamp,
+    /// The permission key that authorized the write, or `None` if it was written by the account
+    /// itself.
+    pub author: Option<PermissionKey>,
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum NodeValue {
+    /// Legacy string value from `0.12.0`.
     Value(ValueAtHeight),
     Node(NodeId),
+    /// Legacy deleted entry from `0.12.0`.
     DeletedEntry(BlockHeight),
+    TypedValue(TypedValueAtHeight),
+    SharedValue(SharedValueAtHeight),
+    DeletedEntryV1(DeletedEntryAtHeight),
 }
 
 impl NodeValue {
-    pub fn into_current_height(mut self) -> Self {
-        match &mut self {
-            NodeValue::Value(v) => {
-                v.block_height = env::block_height();
-            }
-            NodeValue::Node(_) => {}
-            NodeValue::DeletedEntry(v) => {
-                *v = env::block_height();
-            }
-        };
-        self
+    /// Returns the deleted entry at the current block.
+    pub fn new_deleted_entry(author: Option<PermissionKey>) -> Self {
+        NodeValue::DeletedEntryV1(DeletedEntryAtHeight {
+            block_height: env::block_height(),
+            timestamp: env::block_timestamp(),
+            author,
+        })
+    }
+
+    /// Updates the block height and the timestamp of the value to the current block. Legacy
+    /// values are converted to the current variants. The author is kept.
+    pub fn into_current_height(self) -> Self {
+        let block_height = env::block_height();
+        let timestamp = env::block_timestamp();
+        match self {
+            NodeValue::Value(v) => NodeValue::TypedValue(TypedValueAtHeight {
+                value: TypedValue::String(v.value),
+                block_height,
+                timestamp,
+                author: None,
+            }),
+            NodeValue::Node(node_id) => NodeValue::Node(node_id),
+            NodeValue::DeletedEntry(_) => Self::new_deleted_entry(None),
+            NodeValue::DeletedEntryV1(v) => Self::new_deleted_entry(v.author),
+            NodeValue::TypedValue(v) => NodeValue::TypedValue(TypedValueAtHeight {
+                value: v.value,
+                block_height,
+                timestamp,
+                author: v.author,
+            }),
+            NodeValue::SharedValue(v) => NodeValue::SharedValue(SharedValueAtHeight {
+                hash: v.hash,
+                block_height,
+                timestamp,
+                author: v.author,
+            }),
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        matches!(
+            self,
+            NodeValue::DeletedEntry(_) | NodeValue::DeletedEntryV1(_)
+        )
+    }
+
+    /// Returns the value in the form returned by `get_nodes`. String values are returned as the
+    /// legacy `Value`, so the readers of the `0.12.0` format keep working. Compressed strings are
+    /// returned as stored, unless `decompress` is `true`.
+    pub fn into_export(self, decompress: bool) -> Self {
+        match self {
+            NodeValue::TypedValue(TypedValueAtHeight {
+                value: TypedValue::String(value),
+                block_height,
+                ..
+            }) => NodeValue::Value(ValueAtHeight {
+                value,
+                block_height,
+            }),
+            NodeValue::TypedValue(TypedValueAtHeight {
+                value: TypedValue::CompressedString(bytes),
+                block_height,
+                ..
+            }) if decompress => NodeValue::Value(ValueAtHeight {
+                value: decompress_string(&bytes.0),
+                block_height,
+            }),
+            node_value => node_value,
+        }
+    }
+
+    /// Returns the number of bytes of the serialized value.
+    pub fn stored_size(&self) -> u64 {
+        self.try_to_vec().unwrap().len() as u64
     }
 
     pub fn get_block_height(&self) -> Option<BlockHeight> {
@@ -38,6 +227,31 @@ impl NodeValue {
             NodeValue::Value(v) => Some(v.block_height),
             NodeValue::Node(_) => None,
             NodeValue::DeletedEntry(v) => Some(*v),
+            NodeValue::TypedValue(v) => Some(v.block_height),
+            NodeValue::SharedValue(v) => Some(v.block_height),
+            NodeValue::DeletedEntryV1(v) => Some(v.block_height),
+        }
+    }
+
+    /// Returns the permission key that authorized the write of the value. It's `None` for the
+    /// values written by the account itself, the legacy values and nodes.
+    pub fn get_author(&self) -> Option<&PermissionKey> {
+        match self {
+            NodeValue::Value(_) | NodeValue::Node(_) | NodeValue::DeletedEntry(_) => None,
+            NodeValue::TypedValue(v) => v.author.as_ref(),
+            NodeValue::SharedValue(v) => v.author.as_ref(),
+            NodeValue::DeletedEntryV1(v) => v.author.as_ref(),
+        }
+    }
+
+    /// Returns the timestamp of the block when the value was written. It's unknown for the legacy
+    /// values and for nodes.
+    pub fn get_timestamp(&self) -> Option<Timestamp> {
+        match self {
+            NodeValue::Value(_) | NodeValue::Node(_) | NodeValue::DeletedEntry(_) => None,
+            NodeValue::TypedValue(v) => Some(v.timestamp),
+            NodeValue::SharedValue(v) => Some(v.timestamp),
+            NodeValue::DeletedEntryV1(v) => Some(v.timestamp),
         }
     }
 }
@@ -48,6 +262,43 @@ pub struct Node {
     pub node_id: NodeId,
     pub block_height: BlockHeight,
     pub children: UnorderedMap<String, NodeValue>,
+    /// The number of children that are deleted entries. It's `None` for the nodes created before
+    /// `0.13.0`. Counting them would read all the children, so they stay `None` and the deleted
+    /// children are only counted by the views that need them.
+    pub num_deleted_children: Option<u32>,
+    /// The keys of the children in the lexicographic order. Only maintained for the nodes that
+    /// were written with the `:ordered` marker.
+    pub ordered_keys: Option<TreeMap<String, ()>>,
+    /// The timestamp of the block when the node was last updated. It's `None` for the nodes that
+    /// were last written before `0.13.0`.
+    pub timestamp: Option<Timestamp>,
+    /// The keys of the children ordered by the block height of their last change. Only maintained
+    /// for the nodes that were written with the `:recent` marker.
+    pub recent_keys: Option<RecentKeys>,
+}
+
+/// The index of the children of a node by the block height of their last change. The block height
+/// of a child node is the block height of the last write within its subtree, or an upper bound of
+/// it.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct RecentKeys {
+    /// The block height of the last change of every child.
+    pub heights: LookupMap<String, BlockHeight>,
+    /// The block heights and the keys of the children, ordered by the block height.
+    pub keys: TreeMap<(BlockHeight, String), ()>,
+}
+
+impl RecentKeys {
+    /// Moves the child to the given block height in the index.
+    fn update(&mut self, key: &String, block_height: BlockHeight) {
+        if let Some(prev_block_height) = self.heights.insert(key, &block_height) {
+            if prev_block_height == block_height {
+                return;
+            }
+            self.keys.remove(&(prev_block_height, key.clone()));
+        }
+        self.keys.insert(&(block_height, key.clone()), &());
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -60,14 +311,28 @@ pub struct PartialNode {
     pub num_children: u32,
 }
 
+/// The nodes changed after a block height, see `get_nodes_since`.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NodesSince {
+    pub nodes: Vec<PartialNode>,
+    /// The sequence number of the journal entry to continue from with the next page.
+    pub next_seq: u64,
+    /// Set if some changes after the block height were pruned from the journal, so the nodes
+    /// should be loaded again with `get_nodes`.
+    pub pruned: bool,
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum VNode {
+    V0(NodeV0),
     Current(Node),
 }
 
 impl From<VNode> for Node {
     fn from(v: VNode) -> Self {
         match v {
+            VNode::V0(c) => c.into(),
             VNode::Current(c) => c,
         }
     }
@@ -81,37 +346,189 @@ impl From<Node> for VNode {
 
 impl Node {
     pub fn new(node_id: NodeId, value: Option<NodeValue>) -> Self {
-        let mut children = UnorderedMap::new(StorageKey::Node { node_id });
+        let mut node = Self {
+            node_id,
+            block_height: env::block_height(),
+            children: UnorderedMap::new(StorageKey::Node { node_id }),
+            num_deleted_children: Some(0),
+            ordered_keys: None,
+            timestamp: Some(env::block_timestamp()),
+            recent_keys: None,
+        };
         if let Some(value) = value {
             require!(
                 !matches!(value, NodeValue::Node(_)),
                 "Invariant: empty key value can't be a node"
             );
-            children.insert(&EMPTY_KEY.to_string(), &value);
+            node.insert_child(&EMPTY_KEY.to_string(), &value);
         }
-        Self {
-            node_id,
-            block_height: env::block_height(),
-            children,
+        node
+    }
+
+    /// Inserts the child and keeps the number of deleted children, the ordered keys and the recent
+    /// keys up to date. Returns the previous value of the child.
+    pub fn insert_child(&mut self, key: &String, value: &NodeValue) -> Option<NodeValue> {
+        let prev_value = self.children.insert(key, value);
+        if prev_value.is_none() {
+            if let Some(ordered_keys) = self.ordered_keys.as_mut() {
+                ordered_keys.insert(key, &());
+            }
+        }
+        if let Some(recent_keys) = self.recent_keys.as_mut() {
+            let block_height = value.get_block_height().unwrap_or_else(env::block_height);
+            recent_keys.update(key, block_height);
+        }
+        if let Some(num_deleted_children) = self.num_deleted_children.as_mut() {
+            if prev_value.as_ref().map_or(false, |v| v.is_deleted()) {
+                *num_deleted_children -= 1;
+            }
+            if value.is_deleted() {
+                *num_deleted_children += 1;
+            }
         }
+        prev_value
     }
 
-    pub fn set(&mut self, key: &String, value: &near_sdk::serde_json::Value) {
-        let value = if let Some(s) = value.as_str() {
-            NodeValue::Value(ValueAtHeight {
-                value: s.to_string(),
-                block_height: env::block_height(),
-            })
-        } else if value.is_null() {
-            NodeValue::DeletedEntry(env::block_height())
+    /// Returns the number of children, including the value of the node itself under the empty
+    /// key. Deleted entries are only counted when `include_deleted` is `true`.
+    pub fn num_children(&self, include_deleted: bool) -> u64 {
+        if include_deleted {
+            return self.children.len();
+        }
+        let num_deleted_children = match self.num_deleted_children {
+            Some(num_deleted_children) => num_deleted_children as u64,
+            None => self.count_deleted_children(),
+        };
+        self.children.len() - num_deleted_children
+    }
+
+    /// Returns true if the node has no children, except for its own value under the empty key.
+    /// The indexes of the children can only be added to such nodes, so adding them doesn't read
+    /// all the children.
+    fn is_without_keys(&self) -> bool {
+        match self.children.len() {
+            0 => true,
+            1 => self.children.get(&EMPTY_KEY.to_string()).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Starts or stops maintaining the ordered keys of the children. The index can only be added
+    /// to a new node, or a node without keys.
+    pub fn set_ordered(&mut self, ordered: bool) {
+        match (ordered, self.ordered_keys.as_mut()) {
+            (true, None) => {
+                require!(
+                    self.is_without_keys(),
+                    "The :ordered marker can only be added to a node without keys"
+                );
+                let mut ordered_keys = TreeMap::new(StorageKey::OrderedChildren {
+                    node_id: self.node_id,
+                });
+                for key in self.children.keys() {
+                    ordered_keys.insert(&key, &());
+                }
+                self.ordered_keys = Some(ordered_keys);
+            }
+            (false, Some(ordered_keys)) => {
+                ordered_keys.clear();
+                self.ordered_keys = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the child node to the current block height in the recent keys. It should be called
+    /// when the child node is updated.
+    pub fn touch_child(&mut self, key: &String) {
+        if let Some(recent_keys) = self.recent_keys.as_mut() {
+            recent_keys.update(key, env::block_height());
+        }
+    }
+
+    /// Starts or stops maintaining the recent keys of the children. The index can only be added to
+    /// a new node, or a node without keys.
+    pub fn set_recent(&mut self, recent: bool) {
+        match (recent, self.recent_keys.as_mut()) {
+            (true, None) => {
+                require!(
+                    self.is_without_keys(),
+                    "The :recent marker can only be added to a node without keys"
+                );
+                let mut recent_keys = RecentKeys {
+                    heights: LookupMap::new(StorageKey::RecentChildHeights {
+                        node_id: self.node_id,
+                    }),
+                    keys: TreeMap::new(StorageKey::RecentChildren {
+                        node_id: self.node_id,
+                    }),
+                };
+                for (key, value) in self.children.iter() {
+                    let block_height = value.get_block_height().unwrap_or(self.block_height);
+                    recent_keys.update(&key, block_height);
+                }
+                self.recent_keys = Some(recent_keys);
+            }
+            (false, Some(recent_keys)) => {
+                for ((_, key), _) in recent_keys.keys.to_vec() {
+                    recent_keys.heights.remove(&key);
+                }
+                recent_keys.keys.clear();
+                self.recent_keys = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn count_deleted_children(&self) -> u64 {
+        self.children.values().filter(|v| v.is_deleted()).count() as u64
+    }
+
+    /// Sets the leaf value for the given key. Large values are replaced with references to shared
+    /// blobs, the blob references changes are recorded in `blob_changes`. The `author` is the
+    /// permission key that authorized the write.
+    pub fn set(
+        &mut self,
+        key: &String,
+        value: &Value,
+        author: &Option<PermissionKey>,
+        blob_changes: &mut BlobChanges,
+    ) {
+        let block_height = env::block_height();
+        let timestamp = env::block_timestamp();
+        let author = author.clone();
+        let value = if value.is_null() {
+            NodeValue::new_deleted_entry(author)
         } else {
-            unreachable!("Invariant: value must be a string or null");
+            let value =
+                TypedValue::from_json(value).expect("Invariant: value must be a leaf value");
+            let serialized_value = value.try_to_vec().unwrap();
+            if serialized_value.len() >= MIN_SHARED_VALUE_LENGTH {
+                let hash: CryptoHash = env::sha256(&serialized_value).try_into().unwrap();
+                blob_changes.added.push((hash, value));
+                NodeValue::SharedValue(SharedValueAtHeight {
+                    hash: hash.into(),
+                    block_height,
+                    timestamp,
+                    author,
+                })
+            } else {
+                NodeValue::TypedValue(TypedValueAtHeight {
+                    value,
+                    block_height,
+                    timestamp,
+                    author,
+                })
+            }
         };
-        let prev_value = self.children.insert(&key, &value);
-        require!(
-            !matches!(prev_value, Some(NodeValue::Node(_))),
-            "Internal error, the replaced value was a node"
-        );
+        let prev_value = self.insert_child(key, &value);
+        match prev_value {
+            Some(NodeValue::Node(_)) => {
+                env::panic_str("Internal error, the replaced value was a node")
+            }
+            Some(NodeValue::SharedValue(v)) => blob_changes.removed.push(v.hash.into()),
+            _ => {}
+        }
     }
 }
 
@@ -128,9 +545,44 @@ impl Contract {
         self.internal_get_node(node_id).expect("Node is missing")
     }
 
+    /// Returns the number of bytes of the keys and the values in the subtree of the node, including
+    /// deleted entries. Shared values are counted with the full size of their blobs, even though a
+    /// blob is stored once for all its referrers.
+    ///
+    /// The sizes of the subtrees are cached in the context, so every node is loaded once per call,
+    /// even if the sizes of its ancestors are requested as well. The loading of the nodes is
+    /// charged to the gas budget. When it's exceeded, the traversal is stopped at the entry that
+    /// is being visited, and `None` is returned.
+    pub fn internal_get_subtree_size(&self, node: &Node, ctx: &mut MatchContext) -> Option<u64> {
+        if let Some(size) = ctx.subtree_sizes.get(&node.node_id) {
+            return Some(*size);
+        }
+        let mut size = 0;
+        for (key, node_value) in node.children.iter() {
+            size += key.len() as u64;
+            size += match node_value {
+                NodeValue::Node(node_id) => match ctx.subtree_sizes.get(&node_id) {
+                    Some(size) => *size,
+                    None => {
+                        if ctx.budget.is_exceeded() {
+                            ctx.budget.stop();
+                            return None;
+                        }
+                        let inner_node = self.internal_unwrap_node(node_id);
+                        self.internal_get_subtree_size(&inner_node, ctx)?
+                    }
+                },
+                node_value => self.internal_resolve_node_value(node_value).stored_size(),
+            };
+        }
+        ctx.subtree_sizes.insert(node.node_id, size);
+        Some(size)
+    }
+
     pub fn internal_set_node(&mut self, mut node: Node) {
         let node_id = node.node_id;
         node.block_height = env::block_height();
+        node.timestamp = Some(env::block_timestamp());
         self.nodes.insert(&node_id, &node.into());
     }
 }
@@ -141,33 +593,87 @@ impl Contract {
         self.node_count
     }
 
+    /// When `decompress` is `true`, compressed string values are returned as regular string
+    /// values. Otherwise they are returned as stored.
     pub fn get_nodes(
         &self,
         from_index: Option<u32>,
         limit: Option<u32>,
+        decompress: Option<bool>,
     ) -> Vec<Option<PartialNode>> {
         let from_index = from_index.unwrap_or(0);
         let limit = limit.unwrap_or(self.node_count);
         (from_index..std::cmp::min(self.node_count, from_index + limit))
-            .map(|node_id| self.get_node(node_id, None, None))
+            .map(|node_id| self.get_node(node_id, None, None, decompress))
             .collect()
     }
 
+    /// Returns the nodes that were changed after the given block height, so a mirror can resync
+    /// after downtime. The changed nodes are found in the change journal: the first entry after
+    /// the block height is found by a binary search, or the scan continues from `from_seq` of the
+    /// next page. Up to `limit` entries are scanned, `DEFAULT_NODES_SINCE_LIMIT` by default, and
+    /// every changed node is returned once with its current state. A removed node is reported as
+    /// a deleted entry of its changed parent.
+    pub fn get_nodes_since(
+        &self,
+        block_height: BlockHeight,
+        from_seq: Option<u64>,
+        limit: Option<u32>,
+        decompress: Option<bool>,
+    ) -> NodesSince {
+        let from_seq = match from_seq {
+            Some(from_seq) => std::cmp::max(from_seq, self.journal.first_seq),
+            None => self.journal.first_seq_after(block_height),
+        };
+        let limit = limit.unwrap_or(DEFAULT_NODES_SINCE_LIMIT);
+        let next_seq = std::cmp::min(self.journal.next_seq, from_seq.saturating_add(limit as u64));
+        let mut node_ids = vec![];
+        let mut seen_node_ids = HashSet::new();
+        for seq in from_seq..next_seq {
+            if let Some(entry) = self.journal.entries.get(&seq) {
+                let entry: JournalEntry = entry.into();
+                if entry.block_height > block_height && seen_node_ids.insert(entry.node_id) {
+                    node_ids.push(entry.node_id);
+                }
+            }
+        }
+        NodesSince {
+            nodes: node_ids
+                .into_iter()
+                .filter_map(|node_id| self.get_node(node_id, None, None, decompress))
+                .collect(),
+            next_seq,
+            pruned: self.journal.pruned_block_height > block_height,
+        }
+    }
+
     pub fn get_node(
         &self,
         node_id: NodeId,
         from_index: Option<u32>,
         limit: Option<u32>,
+        decompress: Option<bool>,
     ) -> Option<PartialNode> {
+        let decompress = decompress.unwrap_or(false);
         Some(if node_id == 0 {
-            partial_node_view(&self.root_node, from_index, limit)
+            partial_node_view(&self.root_node, from_index, limit, decompress)
         } else {
-            partial_node_view(&self.internal_get_node(node_id)?, from_index, limit)
+            partial_node_view(
+                &self.internal_get_node(node_id)?,
+                from_index,
+                limit,
+                decompress,
+            )
         })
     }
 }
 
-fn partial_node_view(node: &Node, from_index: Option<u32>, limit: Option<u32>) -> PartialNode {
+fn partial_node_view(
+    node: &Node,
+    from_index: Option<u32>,
+    limit: Option<u32>,
+    decompress: bool,
+) -> PartialNode {
     let num_children = node.children.len() as _;
     let from_index = from_index.unwrap_or(0);
     let limit = limit.unwrap_or(num_children);
@@ -175,10 +681,8 @@ fn partial_node_view(node: &Node, from_index: Option<u32>, limit: Option<u32>) -
     let values = node.children.values_as_vector();
     let children = (from_index..std::cmp::min(num_children, from_index + limit))
         .map(|index| {
-            (
-                keys.get(index as _).unwrap(),
-                values.get(index as _).unwrap(),
-            )
+            let value = values.get(index as _).unwrap();
+            (keys.get(index as _).unwrap(), value.into_export(decompress))
         })
         .collect();
     PartialNode {
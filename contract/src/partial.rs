@@ -0,0 +1,143 @@
+use crate::*;
+use near_sdk::json_types::U64;
+use near_sdk::serde_json::{Map, Value};
+use near_sdk::Gas;
+
+/// The marker of a partial result of `get` or `keys` that was stopped by the gas budget.
+pub const KEY_INCOMPLETE: &str = ":incomplete";
+/// The cursor returned with the `:incomplete` marker to resume the traversal from.
+pub const KEY_CURSOR: &str = ":cursor";
+
+/// The gas that is left after the default budget to serialize the result.
+pub const GAS_RESERVED_FOR_RESULT: Gas = Gas(50 * Gas::ONE_TERA.0);
+
+/// The gas budget of a traversal of the key patterns.
+///
+/// The traversal visits the matched entries level by level, the first level being the list of
+/// patterns. The cursor is the list of indices of the visited entries at every level, e.g.
+/// `0/3/17`. When the budget is exhausted before visiting an entry, the cursor points to this
+/// entry, so the traversal can be resumed from it with the same patterns.
+#[derive(Default)]
+pub struct GasBudget {
+    /// The used gas after which the traversal stops. No limit if `None`.
+    max_gas: Option<Gas>,
+    /// The cursor to resume the traversal from.
+    resume: Vec<u32>,
+    /// The indices of the entries that are being visited at every level.
+    indices: Vec<u32>,
+    /// Whether the traversal was stopped by the budget.
+    pub incomplete: bool,
+    /// The cursor of the first entry that was not visited.
+    pub cursor: Option<Vec<u32>>,
+}
+
+impl GasBudget {
+    /// Creates the budget of a view call. By default, the budget is the prepaid gas without
+    /// `GAS_RESERVED_FOR_RESULT`.
+    pub fn new(gas_budget: Option<U64>, cursor: Option<&str>) -> Self {
+        let max_gas = match gas_budget {
+            Some(gas_budget) => Gas(gas_budget.0),
+            None => {
+                let prepaid_gas = env::prepaid_gas().0;
+                Gas(prepaid_gas.saturating_sub(GAS_RESERVED_FOR_RESULT.0))
+            }
+        };
+        let resume = cursor.map_or(vec![], |cursor| {
+            cursor
+                .split(SEPARATOR)
+                .map(|index| index.parse().expect("Invalid cursor"))
+                .collect()
+        });
+        Self {
+            max_gas: Some(max_gas),
+            resume,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the budget with the same limit for a separate traversal, e.g. of the inner pattern
+    /// of a join, that doesn't resume from the cursor.
+    pub fn nested(&self) -> Self {
+        Self {
+            max_gas: self.max_gas,
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if the used gas exceeds the budget.
+    pub fn is_exceeded(&self) -> bool {
+        match self.max_gas {
+            Some(max_gas) => env::used_gas() >= max_gas,
+            None => false,
+        }
+    }
+
+    /// Starts visiting the entries of the next level. Returns the index of the first entry to
+    /// visit, which is only positive when the traversal is resumed from the cursor.
+    pub fn enter_level(&mut self) -> usize {
+        let level = self.indices.len();
+        let start = if self.resume.len() > level && self.resume[..level] == self.indices[..] {
+            self.resume[level]
+        } else {
+            0
+        };
+        self.indices.push(start);
+        start as usize
+    }
+
+    pub fn leave_level(&mut self) {
+        self.indices.pop();
+    }
+
+    /// Should be called before visiting the entry with the given index at the current level.
+    /// Returns false if the traversal should stop.
+    pub fn visit(&mut self, index: usize) -> bool {
+        if self.incomplete {
+            return false;
+        }
+        *self.indices.last_mut().unwrap() = index as u32;
+        if self.is_exceeded() {
+            self.stop();
+            return false;
+        }
+        true
+    }
+
+    /// Stops the traversal at the entry that is being visited, so the entry is visited again when
+    /// the traversal is resumed from the cursor.
+    pub fn stop(&mut self) {
+        if self.incomplete {
+            return;
+        }
+        self.incomplete = true;
+        self.cursor = Some(self.indices.clone());
+    }
+}
+
+/// Adds the `:incomplete` marker and the `:cursor` to the result of `get` or `keys` if the
+/// traversal was stopped. The flat result gets the markers as the last element of the list.
+pub(crate) fn partial_result(res: Value, budget: &GasBudget) -> Value {
+    if !budget.incomplete {
+        return res;
+    }
+    let mut markers = Map::new();
+    markers.insert(KEY_INCOMPLETE.to_string(), true.into());
+    if let Some(cursor) = &budget.cursor {
+        let cursor: Vec<String> = cursor.iter().map(|index| index.to_string()).collect();
+        markers.insert(
+            KEY_CURSOR.to_string(),
+            cursor.join(&SEPARATOR.to_string()).into(),
+        );
+    }
+    match res {
+        Value::Object(mut res) => {
+            res.extend(markers);
+            Value::Object(res)
+        }
+        Value::Array(mut res) => {
+            res.push(Value::Object(markers));
+            Value::Array(res)
+        }
+        res => res,
+    }
+}
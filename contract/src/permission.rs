@@ -1,8 +1,9 @@
 use crate::*;
+use near_sdk::json_types::U64;
 use near_sdk::{require, PublicKey};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub enum PermissionKey {
     AccountId(AccountId),
@@ -23,6 +24,56 @@ impl Permission {
     }
 }
 
+/// Whether the predecessor can write to a node and its subtree.
+#[derive(Clone)]
+pub enum WriteApproval {
+    Denied,
+    /// The predecessor is the account itself.
+    Owner,
+    /// The write permission to the node or one of its ancestors was granted to the given key.
+    Granted(PermissionKey),
+}
+
+impl WriteApproval {
+    /// Returns the approval of the writes of the predecessor to the account with the given ID,
+    /// before the permissions granted on the nodes are checked.
+    pub fn for_account(account_id: &str) -> Self {
+        if account_id == env::predecessor_account_id().as_str() {
+            WriteApproval::Owner
+        } else {
+            WriteApproval::Denied
+        }
+    }
+
+    pub fn is_approved(&self) -> bool {
+        !matches!(self, WriteApproval::Denied)
+    }
+
+    /// Returns the permission key that authorized the write, or `None` if the account writes
+    /// itself.
+    pub fn author(&self) -> Option<PermissionKey> {
+        match self {
+            WriteApproval::Granted(permission_key) => Some(permission_key.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the approval of the writes to the given child node. If the writes to the parent are
+    /// denied, they are approved by the permission granted on the node, if any.
+    pub fn for_node(
+        &self,
+        node_id: NodeId,
+        writable_nodes: &HashMap<NodeId, PermissionKey>,
+    ) -> Self {
+        match (self, writable_nodes.get(&node_id)) {
+            (WriteApproval::Denied, Some(permission_key)) => {
+                WriteApproval::Granted(permission_key.clone())
+            }
+            (approval, _) => approval.clone(),
+        }
+    }
+}
+
 #[near_bindgen]
 impl Contract {
     #[payable]
@@ -52,6 +103,14 @@ impl Contract {
             .permissions
             .get(&permission_key)
             .unwrap_or_else(|| Permission::Granted(HashSet::new()));
+        let event = GrantWritePermissionEventData {
+            account_id: account_id.clone(),
+            permission_key: permission_key.clone(),
+            keys: keys.clone(),
+            truncated: None,
+            block_height: U64(env::block_height()),
+        };
+        let mut changed_keys = vec![];
         match &mut permission {
             Permission::Granted(node_ids) => {
                 node_ids.extend(keys.into_iter().map(|key| {
@@ -63,22 +122,28 @@ impl Contract {
                         account_id.as_str(),
                         "The path should start with the expected account_id"
                     );
+                    self.limits.assert_depth(path.len());
                     let mut node = Some(self.internal_unwrap_node(account.node_id));
                     for &key in &path[1..] {
                         assert_key_valid(key);
                         let node_value = node.as_ref().unwrap().children.get(&key.to_string());
                         match node_value {
                             None => {
+                                changed_keys
+                                    .push((node.as_ref().unwrap().node_id, key.to_string()));
                                 let node_id = self.create_node_id();
+                                let prev_num_children = node.as_ref().unwrap().children.len();
                                 node.as_mut()
                                     .unwrap()
-                                    .children
-                                    .insert(&key.to_string(), &NodeValue::Node(node_id));
+                                    .insert_child(&key.to_string(), &NodeValue::Node(node_id));
+                                self.limits
+                                    .assert_children(node.as_ref().unwrap(), prev_num_children);
                                 self.internal_set_node(
                                     node.replace(Node::new(node_id, None)).unwrap(),
                                 );
                             }
                             Some(NodeValue::Node(node_id)) => {
+                                node.as_mut().unwrap().touch_child(&key.to_string());
                                 self.internal_set_node(
                                     node.replace(self.internal_unwrap_node(node_id)).unwrap(),
                                 );
@@ -86,13 +151,14 @@ impl Contract {
                             Some(node_value) => {
                                 assert_ne!(
                                     key, EMPTY_KEY,
-                                    "The empty key's value should be a string"
+                                    "The empty key's value should be a leaf value"
                                 );
+                                changed_keys
+                                    .push((node.as_ref().unwrap().node_id, key.to_string()));
                                 let node_id = self.create_node_id();
                                 node.as_mut()
                                     .unwrap()
-                                    .children
-                                    .insert(&key.to_string(), &NodeValue::Node(node_id));
+                                    .insert_child(&key.to_string(), &NodeValue::Node(node_id));
                                 self.internal_set_node(
                                     node.replace(Node::new(node_id, Some(node_value))).unwrap(),
                                 );
@@ -108,6 +174,8 @@ impl Contract {
         };
         account.internal_set_permission(&permission_key, permission);
         self.internal_set_account(account);
+        self.internal_append_journal(account_id.as_str(), changed_keys);
+        event.emit();
     }
 
     pub fn debug_get_permissions(&self, account_id: AccountId) -> Vec<(PermissionKey, Permission)> {
@@ -156,7 +224,7 @@ impl Contract {
                 let mut node = self.internal_unwrap_node(account.node_id);
 
                 for &key in &path[1..] {
-                    let node_value = node.children.get(&key.to_string());
+                    let node_value = node.children.get(&canonicalize_key(key));
                     if let Some(NodeValue::Node(node_id)) = node_value {
                         if node_ids.contains(&node_id) {
                             return true;
@@ -173,23 +241,48 @@ impl Contract {
 }
 
 impl Account {
-    pub fn internal_get_writeable_node_ids(&self) -> HashSet<NodeId> {
-        let mut res = HashSet::new();
-        if let Some(Permission::Granted(node_ids)) = self
-            .permissions
-            .get(&PermissionKey::AccountId(env::predecessor_account_id()))
-        {
-            res.extend(node_ids)
-        };
-        if let Some(Permission::Granted(node_ids)) = self
-            .permissions
-            .get(&PermissionKey::SignerPublicKey(env::signer_account_pk()))
-        {
-            res.extend(node_ids)
-        };
+    /// Returns the nodes that the predecessor was granted the write permission on, with the
+    /// permission key that was granted it. The predecessor account takes precedence over the signer
+    /// public key if both were granted the permission on the same node.
+    pub fn internal_get_writable_nodes(&self) -> HashMap<NodeId, PermissionKey> {
+        let mut res = HashMap::new();
+        let permission_keys = [
+            PermissionKey::SignerPublicKey(env::signer_account_pk()),
+            PermissionKey::AccountId(env::predecessor_account_id()),
+        ];
+        for permission_key in permission_keys {
+            if let Some(Permission::Granted(node_ids)) = self.permissions.get(&permission_key) {
+                for node_id in node_ids {
+                    res.insert(node_id, permission_key.clone());
+                }
+            }
+        }
         res
     }
 
+    /// Removes the given nodes from the granted permissions after the nodes were removed from the
+    /// storage.
+    pub fn internal_remove_node_permissions(&mut self, node_ids: &[NodeId]) {
+        if node_ids.is_empty() {
+            return;
+        }
+        let node_ids: HashSet<NodeId> = node_ids.iter().cloned().collect();
+        for (permission_key, permission) in self.permissions.to_vec() {
+            match permission {
+                Permission::Granted(granted_node_ids) => {
+                    if !granted_node_ids.is_disjoint(&node_ids) {
+                        let granted_node_ids =
+                            granted_node_ids.difference(&node_ids).cloned().collect();
+                        self.internal_set_permission(
+                            &permission_key,
+                            Permission::Granted(granted_node_ids),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     pub fn internal_set_permission(
         &mut self,
         permission_key: &PermissionKey,
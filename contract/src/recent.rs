@@ -0,0 +1,422 @@
+use crate::*;
+use near_sdk::serde_json::{Map, Value};
+use near_sdk::{require, BlockHeight, Timestamp};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OrderBy {
+    /// The most recently changed entries first.
+    BlockHeight,
+}
+
+/// Which entries match the last key of a pattern.
+#[derive(Copy, Clone)]
+pub enum RecentMatchMode {
+    /// The values, like `get` returns them.
+    Values { return_deleted: bool },
+    /// The keys, like `keys` returns them.
+    Keys {
+        return_deleted: bool,
+        values_only: bool,
+    },
+}
+
+/// An entry matching one of the patterns, found in the order of recency.
+pub struct RecentMatch {
+    /// The path of the entry. The value of a node itself is matched at the path of the node.
+    pub path: Vec<String>,
+    pub block_height: BlockHeight,
+    pub timestamp: Option<Timestamp>,
+    pub node_value: NodeValue,
+    /// The ID of the node at the path, if the path points to a node.
+    pub node_id: Option<NodeId>,
+}
+
+enum Candidate {
+    /// A node that was not loaded yet. Its block height is bounded by the block height of the
+    /// parent node, except for the account nodes.
+    Unloaded(NodeId, Position),
+    /// A node that was loaded. Every value in the subtree of a node was written at or before the
+    /// block height of the node, so it bounds the block heights of the matches within the node.
+    Loaded(Node, Position),
+    /// A node with the recent keys, which is expanded one child at a time in the order of the
+    /// index. The entry is the next child to expand, its block height bounds the block heights of
+    /// the matches within the remaining children.
+    Indexed(Node, Position, (BlockHeight, String)),
+    Match(RecentMatch),
+}
+
+/// The position of a node within the pattern.
+struct Position {
+    path: Vec<String>,
+    pattern_index: usize,
+    /// The index of the key of the pattern that is matched against the children of the node. If
+    /// it's equal to the pattern length, then the node itself matches the pattern.
+    key_index: usize,
+}
+
+/// The patterns and the options that are shared by all the candidates.
+struct RecentQuery<'a> {
+    patterns: &'a [Vec<String>],
+    exclusions: &'a Exclusions,
+    mode: RecentMatchMode,
+}
+
+/// Candidates ordered by their block heights. The candidates with the same block height are
+/// ordered by their insertion order.
+#[derive(Default)]
+struct Candidates {
+    heap: BinaryHeap<(BlockHeight, Reverse<usize>)>,
+    candidates: Vec<Option<Candidate>>,
+}
+
+impl Candidates {
+    fn push(&mut self, block_height: BlockHeight, candidate: Candidate) {
+        let index = self.candidates.len();
+        self.candidates.push(Some(candidate));
+        self.heap.push((block_height, Reverse(index)));
+    }
+
+    fn pop(&mut self) -> Option<Candidate> {
+        let (_, Reverse(index)) = self.heap.pop()?;
+        self.candidates[index].take()
+    }
+}
+
+impl Contract {
+    /// Returns up to `limit` entries matching the given patterns, the most recently changed first.
+    ///
+    /// The traversal is best-first: the nodes are only loaded and expanded when their block
+    /// heights are higher than the block heights of all the matches found so far, so the
+    /// subtrees that were not changed recently are not visited. The children of the nodes with the
+    /// recent keys are matched by `*` and `**` in the order of the index, so only the recently
+    /// changed children are loaded. The excluded entries are skipped.
+    ///
+    /// When the gas budget is exceeded, the matches found so far are returned. They are still the
+    /// most recent ones, but there may be fewer than `limit` of them. The patterns should start
+    /// with an account ID.
+    pub fn internal_get_recent(
+        &self,
+        patterns: &[Vec<String>],
+        exclusions: &Exclusions,
+        mode: RecentMatchMode,
+        limit: u32,
+        budget: &mut GasBudget,
+    ) -> Vec<RecentMatch> {
+        let query = RecentQuery {
+            patterns,
+            exclusions,
+            mode,
+        };
+        let mut candidates = Candidates::default();
+        for pattern_index in 0..patterns.len() {
+            // The root node has no index of the accounts by recency, so a wildcard or a range
+            // over the accounts would read all the account nodes.
+            let account_key = &patterns[pattern_index][0];
+            require!(
+                account_key != STAR
+                    && account_key != RECURSIVE_STAR
+                    && parse_range(account_key).is_none(),
+                "The patterns with order_by should start with an account ID"
+            );
+            let position = Position {
+                path: vec![],
+                pattern_index,
+                key_index: 0,
+            };
+            expand_recent(&self.root_node, position, &query, &mut candidates);
+        }
+        let mut res = vec![];
+        let mut paths = HashSet::new();
+        while res.len() < limit as usize {
+            if budget.is_exceeded() {
+                budget.incomplete = true;
+                break;
+            }
+            match candidates.pop() {
+                None => break,
+                Some(Candidate::Match(m)) => {
+                    if paths.insert(m.path.clone()) {
+                        res.push(m);
+                    }
+                }
+                Some(Candidate::Unloaded(node_id, position)) => {
+                    let node = self.internal_unwrap_node(node_id);
+                    candidates.push(node.block_height, Candidate::Loaded(node, position));
+                }
+                Some(Candidate::Loaded(node, position)) => {
+                    let pattern = &patterns[position.pattern_index];
+                    if position.key_index == pattern.len() {
+                        if exclusions.excludes(&position.path) {
+                            continue;
+                        }
+                        if let Some(m) = node_match(node, position.path, mode) {
+                            candidates.push(m.block_height, Candidate::Match(m));
+                        }
+                    } else if let Some(entry) =
+                        first_recent_entry(&node, &pattern[position.key_index])
+                    {
+                        candidates.push(entry.0, Candidate::Indexed(node, position, entry));
+                    } else {
+                        expand_recent(&node, position, &query, &mut candidates);
+                    }
+                }
+                Some(Candidate::Indexed(node, position, entry)) => {
+                    let recent_keys = node.recent_keys.as_ref().unwrap();
+                    let next_entry = recent_keys.keys.lower(&entry);
+                    let (block_height, key) = entry;
+                    if let Some(value) = node.children.get(&key) {
+                        let entry = (key, value);
+                        push_entry(
+                            &node,
+                            &position,
+                            entry,
+                            block_height,
+                            &query,
+                            &mut candidates,
+                        );
+                    }
+                    if let Some(entry) = next_entry {
+                        candidates.push(entry.0, Candidate::Indexed(node, position, entry));
+                    }
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Returns the most recent entry of the recent keys of the node, if the node has them and all its
+/// children match the key of the pattern.
+fn first_recent_entry(node: &Node, pattern_key: &str) -> Option<(BlockHeight, String)> {
+    if pattern_key != STAR && pattern_key != RECURSIVE_STAR {
+        return None;
+    }
+    node.recent_keys.as_ref()?.keys.max()
+}
+
+/// Pushes the candidates for all the children of the node that match the key of the pattern.
+fn expand_recent(
+    node: &Node,
+    position: Position,
+    query: &RecentQuery,
+    candidates: &mut Candidates,
+) {
+    let keys = &query.patterns[position.pattern_index][position.key_index..];
+    let matched_entries = if keys[0] == RECURSIVE_STAR {
+        node.children.to_vec()
+    } else {
+        match_children(node, &keys[0], None)
+    };
+    // The block height of the root node is only updated when an account is created, so it
+    // doesn't bound the block heights of the account nodes.
+    let bound = if node.node_id == 0 {
+        BlockHeight::MAX
+    } else {
+        node.block_height
+    };
+    for entry in matched_entries {
+        push_entry(node, &position, entry, bound, query, candidates);
+    }
+}
+
+/// Pushes the candidate for the child of the node that matches the key of the pattern. The block
+/// height of a child node is bounded by the given block height.
+fn push_entry(
+    node: &Node,
+    position: &Position,
+    (key, value): (String, NodeValue),
+    bound: BlockHeight,
+    query: &RecentQuery,
+    candidates: &mut Candidates,
+) {
+    let keys = &query.patterns[position.pattern_index][position.key_index..];
+    let is_recursive_match_all = keys[0] == RECURSIVE_STAR;
+    if is_recursive_match_all {
+        require!(keys.len() == 1, "'**' pattern can only be used as a suffix")
+    }
+    let mut path = position.path.clone();
+    match value {
+        NodeValue::Node(node_id) => {
+            path.push(key);
+            if query.exclusions.excludes_subtree(&path) {
+                return;
+            }
+            let key_index = if is_recursive_match_all {
+                position.key_index
+            } else {
+                position.key_index + 1
+            };
+            let position = Position {
+                path,
+                pattern_index: position.pattern_index,
+                key_index,
+            };
+            candidates.push(bound, Candidate::Unloaded(node_id, position));
+        }
+        node_value => {
+            if keys.len() > 1 {
+                return;
+            }
+            let node_id = if key == EMPTY_KEY {
+                Some(node.node_id)
+            } else {
+                path.push(key);
+                None
+            };
+            if query.exclusions.excludes(&path) {
+                return;
+            }
+            if let Some(m) = leaf_match(path, node_value, node_id, query.mode) {
+                candidates.push(m.block_height, Candidate::Match(m));
+            }
+        }
+    }
+}
+
+fn leaf_match(
+    path: Vec<String>,
+    node_value: NodeValue,
+    node_id: Option<NodeId>,
+    mode: RecentMatchMode,
+) -> Option<RecentMatch> {
+    let return_deleted = match mode {
+        RecentMatchMode::Values { return_deleted } => return_deleted,
+        RecentMatchMode::Keys { return_deleted, .. } => return_deleted,
+    };
+    if node_value.is_deleted() && !return_deleted {
+        return None;
+    }
+    Some(RecentMatch {
+        path,
+        block_height: node_value.get_block_height()?,
+        timestamp: node_value.get_timestamp(),
+        node_value,
+        node_id,
+    })
+}
+
+/// Returns the match for the node matched by the last key of a pattern.
+fn node_match(node: Node, path: Vec<String>, mode: RecentMatchMode) -> Option<RecentMatch> {
+    let own_value = node.children.get(&EMPTY_KEY.to_string());
+    match mode {
+        RecentMatchMode::Keys {
+            values_only: false, ..
+        } => Some(RecentMatch {
+            path,
+            block_height: node.block_height,
+            timestamp: node.timestamp,
+            node_value: NodeValue::Node(node.node_id),
+            node_id: Some(node.node_id),
+        }),
+        _ => leaf_match(path, own_value?, Some(node.node_id), mode),
+    }
+}
+
+/// Returns the object that should contain the last key of the path, creating the missing objects.
+fn json_map_get_parent_object<'a>(
+    res: &'a mut Map<String, Value>,
+    path: &[String],
+) -> &'a mut Map<String, Value> {
+    path[..path.len() - 1]
+        .iter()
+        .fold(res, |obj, key| json_map_get_inner_object(obj, key.clone()))
+}
+
+impl Contract {
+    /// Implements `get` with the `order_by` option. The budget is shared with the expansion of the
+    /// joins.
+    pub fn internal_get_recent_values(
+        &self,
+        keys: &[String],
+        options: &GetOptions,
+        exclusions: Exclusions,
+        mut budget: GasBudget,
+    ) -> Value {
+        let limit = options
+            .limit
+            .expect("The limit is required to order the results");
+        require!(
+            options.max_depth.is_none(),
+            "max_depth can't be used to order the results"
+        );
+        let mode = RecentMatchMode::Values {
+            return_deleted: options.return_deleted.unwrap_or(false),
+        };
+        require!(
+            options.cursor.is_none(),
+            "The cursor can't be used to order the results"
+        );
+        let patterns = parse_patterns(keys);
+        let matches = self.internal_get_recent(&patterns, &exclusions, mode, limit, &mut budget);
+        if options.flat.unwrap_or(false) {
+            let records = matches
+                .into_iter()
+                .filter_map(|m| {
+                    let node_value = self.internal_resolve_node_value(m.node_value);
+                    let value = node_value_to_json(node_value, options)?;
+                    Some(PathRecord::new(
+                        &m.path,
+                        Some(value),
+                        m.block_height,
+                        m.timestamp,
+                        m.node_id,
+                    ))
+                })
+                .collect();
+            return partial_result(records_to_json(records), &budget);
+        }
+        let mut res = Map::new();
+        for m in matches {
+            let parent = json_map_get_parent_object(&mut res, &m.path);
+            let node_value = self.internal_resolve_node_value(m.node_value);
+            json_map_set_key(parent, m.path.last().unwrap().clone(), node_value, options);
+        }
+        partial_result(Value::Object(res), &budget)
+    }
+
+    /// Implements `keys` with the `order_by` option. The budget is shared with the expansion of the
+    /// joins.
+    pub fn internal_get_recent_keys(
+        &self,
+        keys: &[String],
+        options: &KeysOptions,
+        exclusions: Exclusions,
+        mut budget: GasBudget,
+    ) -> Value {
+        let limit = options
+            .limit
+            .expect("The limit is required to order the results");
+        let mode = RecentMatchMode::Keys {
+            return_deleted: options.return_deleted.unwrap_or(false),
+            values_only: options.values_only.unwrap_or(false),
+        };
+        require!(
+            options.cursor.is_none(),
+            "The cursor can't be used to order the results"
+        );
+        let patterns = parse_patterns(keys);
+        let matches = self.internal_get_recent(&patterns, &exclusions, mode, limit, &mut budget);
+        if options.flat.unwrap_or(false) {
+            let records = matches
+                .into_iter()
+                .map(|m| PathRecord::new(&m.path, None, m.block_height, m.timestamp, m.node_id))
+                .collect();
+            return partial_result(records_to_json(records), &budget);
+        }
+        let mut res = Map::new();
+        for m in matches {
+            let value = match options.return_type.unwrap_or(KeysReturnType::True) {
+                KeysReturnType::True => true.into(),
+                KeysReturnType::BlockHeight => m.block_height.into(),
+                KeysReturnType::NodeId => m.node_id.map_or(Value::Null, |node_id| node_id.into()),
+                KeysReturnType::Timestamp => timestamp_to_json(m.timestamp),
+            };
+            let parent = json_map_get_parent_object(&mut res, &m.path);
+            json_map_set_value(parent, m.path.last().unwrap().clone(), value);
+        }
+        partial_result(Value::Object(res), &budget)
+    }
+}
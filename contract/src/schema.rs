@@ -0,0 +1,309 @@
+use crate::*;
+use near_sdk::require;
+use near_sdk::serde_json::{Map, Value};
+use std::collections::HashMap;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Schema {
+    /// Keys that the node should have after every write. Deleted entries don't count.
+    #[serde(default)]
+    pub required_keys: Vec<String>,
+    /// If provided, only these keys can be written to the node.
+    pub allowed_keys: Option<Vec<String>>,
+    /// The maximum length of the leaf values written to the node. The length of non-string values
+    /// is the length of their JSON representation.
+    pub max_value_length: Option<u32>,
+    /// A regular expression that the leaf values written to the node should match. Non-string
+    /// values are matched against their JSON representation.
+    pub value_pattern: Option<String>,
+}
+
+/// Schemas registered for patterns with the same first segment, keyed by the rest of the pattern.
+pub type Schemas = HashMap<String, Schema>;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VSchemas {
+    Current(Schemas),
+}
+
+impl From<VSchemas> for Schemas {
+    fn from(v: VSchemas) -> Self {
+        match v {
+            VSchemas::Current(c) => c,
+        }
+    }
+}
+
+impl From<Schemas> for VSchemas {
+    fn from(c: Schemas) -> Self {
+        VSchemas::Current(c)
+    }
+}
+
+/// A schema that applies to the account of a write. The value pattern is compiled on the first
+/// use and reused for all nodes of the write.
+pub struct AccountSchema {
+    /// The keys of the pattern after the account ID.
+    pub path: Vec<String>,
+    pub schema: Schema,
+    value_pattern: Option<regex_lite::Regex>,
+}
+
+impl AccountSchema {
+    fn new(rest: String, schema: Schema) -> Self {
+        Self {
+            path: if rest.is_empty() {
+                vec![]
+            } else {
+                rest.split(SEPARATOR).map(|k| k.to_string()).collect()
+            },
+            schema,
+            value_pattern: None,
+        }
+    }
+
+    /// Returns true if the pattern matches the given path, without the account ID.
+    fn matches(&self, path: &[String]) -> bool {
+        self.path.len() == path.len()
+            && self
+                .path
+                .iter()
+                .zip(path)
+                .all(|(pattern_key, key)| key_matches(pattern_key, key))
+    }
+
+    fn compile_value_pattern(&mut self) {
+        if self.value_pattern.is_none() {
+            self.value_pattern = self
+                .schema
+                .value_pattern
+                .as_ref()
+                .map(|p| regex_lite::Regex::new(p).unwrap());
+        }
+    }
+}
+
+impl Schema {
+    fn assert_valid(&self) {
+        if let Some(value_pattern) = &self.value_pattern {
+            if regex_lite::Regex::new(value_pattern).is_err() {
+                env::panic_str("The value pattern is not a valid regular expression");
+            }
+        }
+    }
+
+    /// Validates the object that was written to the given node.
+    fn validate(
+        &self,
+        path: &str,
+        node: &Node,
+        obj: &Map<String, Value>,
+        value_pattern: Option<&regex_lite::Regex>,
+    ) {
+        for (key, value) in obj {
+            if key == EMPTY_KEY {
+                continue;
+            }
+            if let Some(allowed_keys) = &self.allowed_keys {
+                if !allowed_keys.contains(key) {
+                    env::panic_str(&format!(
+                        "The key {} is not allowed by the schema of {}",
+                        key, path
+                    ));
+                }
+            }
+            if !is_leaf_value(value) || value.is_null() {
+                continue;
+            }
+            let text = match value.as_str() {
+                Some(s) => s.to_string(),
+                None => value.to_string(),
+            };
+            if let Some(max_value_length) = self.max_value_length {
+                if text.len() > max_value_length as usize {
+                    env::panic_str(&format!(
+                        "The value of {} exceeds the maximum length {} of the schema of {}",
+                        key, max_value_length, path
+                    ));
+                }
+            }
+            if let Some(value_pattern) = value_pattern {
+                if !value_pattern.is_match(&text) {
+                    env::panic_str(&format!(
+                        "The value of {} doesn't match the value pattern of the schema of {}",
+                        key, path
+                    ));
+                }
+            }
+        }
+        for key in &self.required_keys {
+            match node.children.get(key) {
+                Some(node_value) if !node_value.is_deleted() => {}
+                _ => env::panic_str(&format!(
+                    "The key {} is required by the schema of {}",
+                    key, path
+                )),
+            }
+        }
+    }
+}
+
+impl SetContext {
+    /// Validates the object that was written to the node at the current path.
+    pub fn validate_node(&mut self, node: &Node, obj: &Map<String, Value>) {
+        let path = self.path.join(&SEPARATOR.to_string());
+        for schema in self.schemas.iter_mut() {
+            if schema.matches(&self.path[1..]) {
+                schema.compile_value_pattern();
+                schema
+                    .schema
+                    .validate(&path, node, obj, schema.value_pattern.as_ref());
+            }
+        }
+    }
+
+    /// Asserts that a leaf value can be written to the current path. A path with a schema can
+    /// only contain a node.
+    pub fn assert_leaf_allowed(&self) {
+        if self.schemas.iter().any(|s| s.matches(&self.path[1..])) {
+            env::panic_str(&format!(
+                "The value of {} should be an object matching the schema",
+                self.path.join(&SEPARATOR.to_string())
+            ));
+        }
+    }
+}
+
+impl Contract {
+    /// Validates the node at the given path, starting with the account ID, after the key was
+    /// deleted from it, the same way as `set` validates a `null` value.
+    pub fn internal_validate_deletion(&self, path: &[String], node: &Node, key: &str) {
+        let mut obj = Map::new();
+        obj.insert(key.to_string(), Value::Null);
+        let path_str = path.join(&SEPARATOR.to_string());
+        for mut schema in self.internal_get_account_schemas(&path[0]) {
+            if schema.matches(&path[1..]) {
+                schema.compile_value_pattern();
+                schema
+                    .schema
+                    .validate(&path_str, node, &obj, schema.value_pattern.as_ref());
+            }
+        }
+    }
+
+    /// Returns the schemas that apply to the given account, registered by the account itself and
+    /// for all accounts.
+    pub fn internal_get_account_schemas(&self, account_id: &str) -> Vec<AccountSchema> {
+        [account_id, STAR]
+            .iter()
+            .filter_map(|prefix| self.schemas.get(&prefix.to_string()))
+            .flat_map(|s| Schemas::from(s).into_iter())
+            .map(|(rest, schema)| AccountSchema::new(rest, schema))
+            .collect()
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers the schema for the nodes matching the given pattern, or removes it if the schema
+    /// is not provided. The pattern should start with the predecessor account ID, followed by the
+    /// path to the node. The contract account can register schemas for all accounts using `*` as
+    /// the first segment, e.g. `*/profile`. Other segments can be `*` to match any key, e.g.
+    /// `*/widget/*/metadata`.
+    ///
+    /// Requires at least 1 yoctoNEAR. The attached deposit is added to the account storage balance,
+    /// that covers the storage of the schema.
+    #[payable]
+    pub fn set_schema(&mut self, pattern: String, schema: Option<Schema>) {
+        self.assert_live();
+        let attached_deposit = env::attached_deposit();
+        let predecessor_account_id = env::predecessor_account_id();
+        let is_owner = predecessor_account_id == env::current_account_id();
+        require!(
+            is_owner || attached_deposit > 0,
+            "Requires at least 1 yocto"
+        );
+        let path: Vec<&str> = pattern.split(SEPARATOR).collect();
+        if !is_owner {
+            require!(
+                path[0] == predecessor_account_id.as_str(),
+                "The pattern should start with the predecessor account ID"
+            );
+        } else if path[0] != STAR {
+            require!(
+                env::is_valid_account_id(path[0].as_bytes()),
+                "The pattern should start with an account ID or *"
+            );
+        }
+        for &key in &path[1..] {
+            require!(!key.is_empty(), "The pattern contains an empty key");
+            if key != STAR {
+                assert_key_valid(key);
+            }
+        }
+        if let Some(schema) = &schema {
+            schema.assert_valid();
+        }
+
+        let prefix = path[0].to_string();
+        let rest = path[1..].join(&SEPARATOR.to_string());
+        let mut account = if is_owner {
+            None
+        } else {
+            Some(self.internal_unwrap_account_or_create(&prefix, attached_deposit))
+        };
+        if let Some(account) = account.as_mut() {
+            account.storage_tracker.start();
+        }
+        let mut schemas: Schemas = self
+            .schemas
+            .get(&prefix)
+            .map(|s| s.into())
+            .unwrap_or_default();
+        if let Some(schema) = schema {
+            schemas.insert(rest, schema);
+        } else {
+            schemas.remove(&rest);
+        }
+        if schemas.is_empty() {
+            self.schemas.remove(&prefix);
+        } else {
+            self.schemas.insert(&prefix, &schemas.into());
+        }
+        if let Some(mut account) = account {
+            account.storage_tracker.stop();
+            self.internal_set_account(account);
+        }
+    }
+
+    pub fn get_schema(&self, pattern: String) -> Option<Schema> {
+        let (prefix, rest) = match pattern.split_once(SEPARATOR) {
+            Some((prefix, rest)) => (prefix.to_string(), rest),
+            None => (pattern.clone(), EMPTY_KEY),
+        };
+        self.schemas
+            .get(&prefix)
+            .and_then(|s| Schemas::from(s).remove(rest))
+    }
+
+    /// Returns all schemas with the given first segment of the pattern, e.g. an account ID or `*`.
+    pub fn get_schemas(&self, prefix: String) -> Vec<(String, Schema)> {
+        self.schemas
+            .get(&prefix)
+            .map(|s| {
+                Schemas::from(s)
+                    .into_iter()
+                    .map(|(rest, schema)| {
+                        let pattern = if rest.is_empty() {
+                            prefix.clone()
+                        } else {
+                            format!("{}{}{}", prefix, SEPARATOR, rest)
+                        };
+                        (pattern, schema)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
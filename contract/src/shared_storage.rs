@@ -132,6 +132,11 @@ impl Contract {
             self.internal_set_shared_storage_pool(&owner_id, shared_storage_pool);
         }
         storage_tracker.clear();
+        Event::SharedStoragePoolDeposit(vec![StorageEventData::new(
+            owner_id.as_str(),
+            attached_deposit,
+        )])
+        .emit();
     }
 
     pub fn share_storage(&mut self, account_id: AccountId, max_bytes: StorageUsage) {
@@ -145,6 +150,12 @@ impl Contract {
         if available_bytes < max_bytes {
             env::panic_str("Not enough storage available in the shared storage pool");
         }
+        let event = ShareStorageEventData {
+            pool_id: pool_id.clone(),
+            account_id: account_id.clone(),
+            max_bytes: max_bytes.into(),
+            block_height: env::block_height().into(),
+        };
         if let Some(mut account) = account {
             // The account already exists.
 
@@ -249,6 +260,7 @@ impl Contract {
                 pool_id.clone(),
             );
         }
+        Event::ShareStorage(vec![event]).emit();
     }
 
     /// Returns the storage usage of the given account in bytes and accounts for the shared storage.
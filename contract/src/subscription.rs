@@ -0,0 +1,576 @@
+use crate::*;
+use near_sdk::json_types::U64;
+use near_sdk::serde_json;
+use near_sdk::{BlockHeight, Gas};
+use std::collections::BTreeSet;
+use std::ops::Bound;
+
+/// The gas attached to the calls of a subscription by default.
+pub const DEFAULT_SUBSCRIPTION_GAS: Gas = Gas(10 * Gas::ONE_TERA.0);
+/// The maximum gas attached to a call of a subscription.
+pub const MAX_SUBSCRIPTION_GAS: Gas = Gas(30 * Gas::ONE_TERA.0);
+/// The gas that should be left after scheduling a call, so the write can be completed.
+pub const GAS_RESERVED_AFTER_NOTIFICATION: Gas = Gas(10 * Gas::ONE_TERA.0);
+/// The fixed price of the gas of the calls that is charged from the balance of a subscription.
+pub const SUBSCRIPTION_GAS_PRICE: Balance = 100_000_000;
+/// The maximum number of calls scheduled by a single write or `deliver_notifications`.
+pub const MAX_NOTIFICATIONS_PER_CALL: usize = 10;
+/// The maximum number of the queued notifications checked by a single call. The notifications of
+/// the subscriptions that were called less than their `interval` blocks ago are moved to the end
+/// of the queue.
+pub const MAX_QUEUED_NOTIFICATIONS_CHECKED_PER_CALL: usize = 20;
+/// The maximum number of the subscriptions with the same literal prefix of the pattern, since every
+/// write within the prefix checks all of them.
+pub const MAX_SUBSCRIPTIONS_PER_PREFIX: u32 = 100;
+/// The minimum balance of a subscription after its storage is paid, i.e. 0.01 NEAR.
+pub const MIN_SUBSCRIPTION_BALANCE: Balance = 10_000_000_000_000_000_000_000;
+/// The minimum number of blocks between the calls of a subscription by default.
+pub const DEFAULT_SUBSCRIPTION_INTERVAL: BlockHeight = 1;
+/// The default number of the subscriptions returned by `get_subscriptions`.
+pub const DEFAULT_SUBSCRIPTIONS_LIMIT: u64 = 100;
+/// The maximum total length of the paths passed to a call. Longer lists are compacted the same
+/// way as in the events.
+pub const MAX_NOTIFICATION_PATHS_LENGTH: usize = 2 * 1024;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Subscription {
+    pub pattern: String,
+    /// The keys of the pattern, parsed once when subscribing.
+    #[serde(skip)]
+    pub parsed_pattern: Vec<String>,
+    /// The account that registered the subscription and receives the calls.
+    pub subscriber_id: AccountId,
+    pub method_name: String,
+    /// The gas attached to every call.
+    pub gas: Gas,
+    /// The balance that pays for the gas of the calls.
+    #[serde(with = "u128_dec_format")]
+    pub balance: Balance,
+    /// The part of the deposit that covers the storage of the subscription. It's returned with
+    /// the balance when the subscription is removed.
+    #[serde(with = "u128_dec_format")]
+    pub storage_deposit: Balance,
+    /// The minimum number of blocks between the calls, so the writers can't drain the balance.
+    pub interval: BlockHeight,
+    /// The block height of the last call, if any.
+    pub last_block_height: Option<BlockHeight>,
+    /// The matching paths that were not delivered yet. Its storage is paid from the balance.
+    pub pending: Option<PendingNotification>,
+}
+
+/// The matching paths of the writes since the last call of a subscription.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingNotification {
+    /// The account that made the last of the writes.
+    pub predecessor_id: AccountId,
+    pub written: Vec<PathsEventData>,
+    pub deleted: Vec<PathsEventData>,
+}
+
+/// The literal prefix of the pattern, the subscriber ID and the pattern of a subscription.
+pub type SubscriptionKey = (String, String, String);
+
+/// The keys of the subscriptions with pending notifications, in the order they were queued.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct NotificationQueue {
+    pub keys: LookupMap<u64, SubscriptionKey>,
+    /// The index of the first queued key.
+    pub head: u64,
+    /// The index after the last queued key.
+    pub tail: u64,
+}
+
+impl Default for NotificationQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NotificationQueue {
+    pub fn new() -> Self {
+        Self {
+            keys: LookupMap::new(StorageKey::NotificationQueue),
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    pub fn front(&self) -> Option<SubscriptionKey> {
+        if self.head == self.tail {
+            None
+        } else {
+            self.keys.get(&self.head)
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<SubscriptionKey> {
+        if self.head == self.tail {
+            return None;
+        }
+        let key = self.keys.remove(&self.head);
+        self.head += 1;
+        key
+    }
+
+    pub fn push_back(&mut self, key: &SubscriptionKey) {
+        self.keys.insert(&self.tail, key);
+        self.tail += 1;
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VSubscription {
+    Current(Subscription),
+}
+
+impl From<VSubscription> for Subscription {
+    fn from(v: VSubscription) -> Self {
+        match v {
+            VSubscription::Current(c) => c,
+        }
+    }
+}
+
+impl From<Subscription> for VSubscription {
+    fn from(c: Subscription) -> Self {
+        VSubscription::Current(c)
+    }
+}
+
+/// The arguments of the call to a subscriber.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Notification {
+    pub pattern: String,
+    /// The account that made the last of the delivered writes.
+    pub predecessor_id: AccountId,
+    /// The written paths matching the pattern, grouped by account.
+    pub written: Vec<PathsEventData>,
+    /// The deleted paths matching the pattern, grouped by account.
+    pub deleted: Vec<PathsEventData>,
+}
+
+/// Parses the pattern of a subscription. The first key is an account ID or `*`.
+fn parse_subscription_pattern(pattern: &str) -> Vec<String> {
+    let path = parse_pattern(pattern).expect("The pattern is empty");
+    require!(
+        path[0] == STAR || env::is_valid_account_id(path[0].as_bytes()),
+        "The pattern should start with an account ID or *"
+    );
+    require!(
+        !path[..path.len() - 1].contains(&RECURSIVE_STAR.to_string()),
+        "'**' pattern can only be used as a suffix"
+    );
+    path
+}
+
+/// Returns the keys of the pattern before the first `*`, `**` or range, joined with the separator.
+/// Only the paths within the literal prefix can match the pattern.
+fn literal_prefix(pattern: &[String]) -> String {
+    pattern
+        .iter()
+        .take_while(|key| {
+            key.as_str() != STAR && key.as_str() != RECURSIVE_STAR && parse_range(key).is_none()
+        })
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(&SEPARATOR.to_string())
+}
+
+fn subscription_key(path: &[String], subscriber_id: &AccountId) -> SubscriptionKey {
+    (
+        literal_prefix(path),
+        subscriber_id.to_string(),
+        path.join(&SEPARATOR.to_string()),
+    )
+}
+
+/// Returns the distinct changed paths and the paths of their ancestors, including the empty path,
+/// i.e. the literal prefixes of the patterns that can match the changes.
+fn changed_path_prefixes(changes: &ChangedPaths) -> BTreeSet<String> {
+    let mut prefixes = BTreeSet::new();
+    for path in changes
+        .written
+        .iter()
+        .chain(changes.deleted.iter())
+        .flat_map(|d| d.paths.iter())
+    {
+        prefixes.insert(String::new());
+        for (i, c) in path.char_indices() {
+            if c == SEPARATOR {
+                prefixes.insert(path[..i].to_string());
+            }
+        }
+        prefixes.insert(path.clone());
+    }
+    prefixes
+}
+
+/// Adds the paths to the data of the same accounts without duplicates, and compacts them to
+/// `MAX_NOTIFICATION_PATHS_LENGTH`. If even the account IDs don't fit, the data is replaced with a
+/// single truncated entry for all accounts, i.e. `*`.
+fn merge_paths(data: &mut Vec<PathsEventData>, other: Vec<PathsEventData>) {
+    for o in other {
+        match data.iter_mut().find(|d| d.account_id == o.account_id) {
+            Some(d) => {
+                for path in o.paths {
+                    if !d.paths.contains(&path) {
+                        d.paths.push(path);
+                    }
+                }
+                if o.truncated.is_some() {
+                    d.truncated = o.truncated;
+                }
+            }
+            None => data.push(o),
+        }
+    }
+    compact_paths(data, MAX_NOTIFICATION_PATHS_LENGTH);
+    if paths_length(data) > MAX_NOTIFICATION_PATHS_LENGTH {
+        *data = truncated_paths_marker(data);
+    }
+}
+
+/// Returns the paths of the data that match the pattern.
+fn filter_paths(pattern: &[String], data: &[PathsEventData]) -> Vec<PathsEventData> {
+    data.iter()
+        .filter_map(|d| {
+            let paths: Vec<String> = d
+                .paths
+                .iter()
+                .filter(|path| {
+                    let path: Vec<String> = path.split(SEPARATOR).map(|k| k.to_string()).collect();
+                    path_matches(pattern, &path)
+                })
+                .cloned()
+                .collect();
+            if paths.is_empty() {
+                None
+            } else {
+                Some(PathsEventData::new(&d.account_id, paths))
+            }
+        })
+        .collect()
+}
+
+impl Contract {
+    /// Returns the subscriptions with the given literal prefix of the pattern in the order of the
+    /// subscriber IDs.
+    fn internal_get_subscriptions(
+        &self,
+        prefix: String,
+    ) -> impl Iterator<Item = (SubscriptionKey, Subscription)> + '_ {
+        let from = (prefix.clone(), String::new(), String::new());
+        self.subscriptions
+            .range((Bound::Included(from), Bound::Unbounded))
+            .take_while(move |(key, _)| key.0 == prefix)
+            .map(|(key, subscription)| (key, subscription.into()))
+    }
+
+    /// Removes the subscription and returns its balance and storage deposit to the subscriber.
+    fn internal_remove_subscription(&mut self, key: &SubscriptionKey, subscription: Subscription) {
+        self.subscriptions.remove(key);
+        let num_subscriptions = self.subscription_prefixes.get(&key.0).unwrap_or(0);
+        if num_subscriptions > 1 {
+            self.subscription_prefixes
+                .insert(&key.0, &(num_subscriptions - 1));
+        } else {
+            self.subscription_prefixes.remove(&key.0);
+        }
+        let refund = subscription.balance + subscription.storage_deposit;
+        if refund > 0 {
+            Promise::new(subscription.subscriber_id).transfer(refund);
+        }
+    }
+
+    /// Saves the subscription and pays for the storage changed since the tracker was started from
+    /// the balance. The released storage is returned to the balance. A subscription with the
+    /// balance that doesn't cover the storage is removed.
+    fn internal_save_subscription(
+        &mut self,
+        key: &SubscriptionKey,
+        mut subscription: Subscription,
+        mut storage_tracker: StorageTracker,
+    ) {
+        self.subscriptions.insert(key, &subscription.clone().into());
+        storage_tracker.stop();
+        if storage_tracker.bytes_added > storage_tracker.bytes_released {
+            let storage_cost =
+                Balance::from(storage_tracker.bytes_added - storage_tracker.bytes_released)
+                    * env::storage_byte_cost();
+            storage_tracker.clear();
+            if subscription.balance < storage_cost {
+                self.internal_remove_subscription(key, subscription);
+                return;
+            }
+            subscription.balance -= storage_cost;
+            subscription.storage_deposit += storage_cost;
+        } else {
+            let storage_refund = std::cmp::min(
+                Balance::from(storage_tracker.bytes_released - storage_tracker.bytes_added)
+                    * env::storage_byte_cost(),
+                subscription.storage_deposit,
+            );
+            storage_tracker.clear();
+            subscription.storage_deposit -= storage_refund;
+            subscription.balance += storage_refund;
+        }
+        self.subscriptions.insert(key, &subscription.into());
+    }
+
+    /// Adds the matching paths of the changes to the pending notifications of the subscriptions,
+    /// and then delivers the queued notifications. Only the subscriptions with the literal prefixes
+    /// of the changed paths are checked.
+    pub fn internal_notify_subscribers(&mut self, changes: &ChangedPaths) {
+        let predecessor_id = env::predecessor_account_id();
+        for prefix in changed_path_prefixes(changes) {
+            if !self.subscription_prefixes.contains_key(&prefix) {
+                continue;
+            }
+            let subscriptions: Vec<(SubscriptionKey, Subscription)> =
+                self.internal_get_subscriptions(prefix).collect();
+            for (key, mut subscription) in subscriptions {
+                let written = filter_paths(&subscription.parsed_pattern, &changes.written);
+                let deleted = filter_paths(&subscription.parsed_pattern, &changes.deleted);
+                if written.is_empty() && deleted.is_empty() {
+                    continue;
+                }
+                let mut storage_tracker = StorageTracker::default();
+                storage_tracker.start();
+                let mut pending = match subscription.pending.take() {
+                    Some(pending) => pending,
+                    None => {
+                        self.notification_queue.push_back(&key);
+                        PendingNotification {
+                            predecessor_id: predecessor_id.clone(),
+                            written: vec![],
+                            deleted: vec![],
+                        }
+                    }
+                };
+                pending.predecessor_id = predecessor_id.clone();
+                merge_paths(&mut pending.written, written);
+                merge_paths(&mut pending.deleted, deleted);
+                subscription.pending = Some(pending);
+                self.internal_save_subscription(&key, subscription, storage_tracker);
+            }
+        }
+        self.internal_deliver_notifications();
+    }
+
+    /// Schedules the calls to the subscribers with the queued notifications, while the call has
+    /// enough prepaid gas left. A notification of a subscription that was called less than its
+    /// `interval` blocks ago is moved to the end of the queue. A subscription with the balance that
+    /// doesn't cover the gas of a call is removed. The gas of the calls is charged from the
+    /// balances of the subscriptions and transferred to the signer, who paid for it.
+    fn internal_deliver_notifications(&mut self) {
+        let block_height = env::block_height();
+        let mut num_notifications = 0;
+        let mut total_cost: Balance = 0;
+        for _ in 0..MAX_QUEUED_NOTIFICATIONS_CHECKED_PER_CALL {
+            if num_notifications >= MAX_NOTIFICATIONS_PER_CALL {
+                break;
+            }
+            let key = match self.notification_queue.front() {
+                Some(key) => key,
+                None => break,
+            };
+            let mut subscription: Subscription = match self
+                .subscriptions
+                .get(&key)
+                .map(Subscription::from)
+                .filter(|subscription| subscription.pending.is_some())
+            {
+                Some(subscription) => subscription,
+                None => {
+                    // The subscription was removed or its notification was already delivered.
+                    self.notification_queue.pop_front();
+                    continue;
+                }
+            };
+            let cost = Balance::from(subscription.gas.0) * SUBSCRIPTION_GAS_PRICE;
+            if subscription.balance < cost {
+                self.notification_queue.pop_front();
+                self.internal_remove_subscription(&key, subscription);
+                continue;
+            }
+            let is_within_interval = subscription.last_block_height.map_or(false, |h| {
+                block_height < h.saturating_add(subscription.interval)
+            });
+            if is_within_interval {
+                self.notification_queue.pop_front();
+                self.notification_queue.push_back(&key);
+                continue;
+            }
+            let remaining_gas = env::prepaid_gas().0.saturating_sub(env::used_gas().0);
+            if remaining_gas < subscription.gas.0 + GAS_RESERVED_AFTER_NOTIFICATION.0 {
+                break;
+            }
+            let mut storage_tracker = StorageTracker::default();
+            storage_tracker.start();
+            self.notification_queue.pop_front();
+            let pending = subscription.pending.take().unwrap();
+            let notification = Notification {
+                pattern: subscription.pattern.clone(),
+                predecessor_id: pending.predecessor_id,
+                written: pending.written,
+                deleted: pending.deleted,
+            };
+            Promise::new(subscription.subscriber_id.clone()).function_call(
+                subscription.method_name.clone(),
+                serde_json::to_vec(&notification).unwrap(),
+                0,
+                subscription.gas,
+            );
+            subscription.balance -= cost;
+            subscription.last_block_height = Some(block_height);
+            self.internal_save_subscription(&key, subscription, storage_tracker);
+            total_cost += cost;
+            num_notifications += 1;
+        }
+        if total_cost > 0 {
+            Promise::new(env::signer_account_id()).transfer(total_cost);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Registers a subscription of the predecessor to the writes matching the given pattern, e.g.
+    /// `*/index/notify`. The matching paths of every write are queued, and a call of the method on
+    /// the predecessor with the `Notification` as the arguments is scheduled at most once per
+    /// `interval` blocks. Subscribing again to the same pattern updates the method, the gas and the
+    /// interval, and adds the deposit to the balance. Changing the method or the gas requires at
+    /// least 1 yoctoNEAR.
+    ///
+    /// The attached deposit covers the storage of the subscription, and the rest is the balance
+    /// that pays for the gas of the calls at `SUBSCRIPTION_GAS_PRICE` and the storage of the
+    /// pending notification. The balance should be at least `MIN_SUBSCRIPTION_BALANCE`.
+    #[payable]
+    pub fn subscribe(
+        &mut self,
+        pattern: String,
+        method_name: String,
+        gas: Option<U64>,
+        interval: Option<U64>,
+    ) {
+        self.assert_live();
+        let attached_deposit = env::attached_deposit();
+        let gas = gas.map_or(DEFAULT_SUBSCRIPTION_GAS, |gas| Gas(gas.0));
+        require!(
+            gas <= MAX_SUBSCRIPTION_GAS,
+            format!("The gas exceeds the maximum {}", MAX_SUBSCRIPTION_GAS.0)
+        );
+        let interval = interval.map_or(DEFAULT_SUBSCRIPTION_INTERVAL, |interval| interval.0);
+        require!(interval > 0, "The interval should be at least 1 block");
+        let path = parse_subscription_pattern(&pattern);
+        let subscriber_id = env::predecessor_account_id();
+        let key = subscription_key(&path, &subscriber_id);
+        let mut subscription = match self.subscriptions.get(&key) {
+            Some(subscription) => {
+                let mut subscription: Subscription = subscription.into();
+                require!(
+                    attached_deposit > 0
+                        || (subscription.method_name == method_name && subscription.gas == gas),
+                    "Changing the method or the gas requires at least 1 yoctoNEAR"
+                );
+                subscription.method_name = method_name;
+                subscription.gas = gas;
+                subscription.interval = interval;
+                subscription.balance += attached_deposit;
+                subscription
+            }
+            None => Subscription {
+                pattern: key.2.clone(),
+                parsed_pattern: path,
+                subscriber_id,
+                method_name,
+                gas,
+                balance: attached_deposit,
+                storage_deposit: 0,
+                interval,
+                last_block_height: None,
+                pending: None,
+            },
+        };
+        let mut storage_tracker = StorageTracker::default();
+        storage_tracker.start();
+        if !self.subscriptions.contains_key(&key) {
+            let num_subscriptions = self.subscription_prefixes.get(&key.0).unwrap_or(0);
+            require!(
+                num_subscriptions < MAX_SUBSCRIPTIONS_PER_PREFIX,
+                format!(
+                    "The number of the subscriptions with the same literal prefix can't exceed {}",
+                    MAX_SUBSCRIPTIONS_PER_PREFIX
+                )
+            );
+            self.subscription_prefixes
+                .insert(&key.0, &(num_subscriptions + 1));
+        }
+        self.subscriptions
+            .insert(&key, &subscription.clone().into());
+        storage_tracker.stop();
+        if storage_tracker.bytes_added > storage_tracker.bytes_released {
+            let storage_cost =
+                Balance::from(storage_tracker.bytes_added - storage_tracker.bytes_released)
+                    * env::storage_byte_cost();
+            require!(
+                subscription.balance >= storage_cost,
+                "The attached deposit doesn't cover the storage of the subscription"
+            );
+            subscription.balance -= storage_cost;
+            subscription.storage_deposit += storage_cost;
+        }
+        storage_tracker.clear();
+        require!(
+            subscription.balance >= MIN_SUBSCRIPTION_BALANCE,
+            format!(
+                "The balance of the subscription should be at least {}",
+                MIN_SUBSCRIPTION_BALANCE
+            )
+        );
+        self.subscriptions.insert(&key, &subscription.into());
+    }
+
+    /// Removes the subscription of the predecessor to the given pattern. The balance and the
+    /// storage deposit are returned to the predecessor. Requires 1 yoctoNEAR.
+    #[payable]
+    pub fn unsubscribe(&mut self, pattern: String) {
+        self.assert_live();
+        assert_one_yocto();
+        let path = parse_subscription_pattern(&pattern);
+        let key = subscription_key(&path, &env::predecessor_account_id());
+        let subscription: Subscription = self
+            .subscriptions
+            .get(&key)
+            .expect("The subscription doesn't exist")
+            .into();
+        self.internal_remove_subscription(&key, subscription);
+    }
+
+    /// Delivers the queued notifications with the prepaid gas of this call. The gas of the calls
+    /// is charged from the balances of the subscriptions and transferred to the signer.
+    pub fn deliver_notifications(&mut self) {
+        self.assert_live();
+        self.internal_deliver_notifications();
+    }
+
+    /// Returns the subscriptions with the given literal prefix of the pattern, i.e. the keys before
+    /// the first `*`, `**` or range, e.g. `alice.near/post` for `alice.near/post/*` or the empty
+    /// string for `*/index/notify`, in the order of the subscriber IDs from a given index up to a
+    /// given limit.
+    pub fn get_subscriptions(
+        &self,
+        prefix: String,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Subscription> {
+        self.internal_get_subscriptions(prefix)
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(DEFAULT_SUBSCRIPTIONS_LIMIT) as usize)
+            .map(|(_, subscription)| subscription)
+            .collect()
+    }
+}
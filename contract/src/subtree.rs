@@ -0,0 +1,348 @@
+use crate::*;
+use near_sdk::require;
+use near_sdk::serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Parses the path of a `copy` or a `rename` call. The first key is the account ID, and the path
+/// should point to a key within the account.
+fn parse_path(path: &str) -> Vec<String> {
+    let path: Vec<String> = path.split(SEPARATOR).map(|k| k.to_string()).collect();
+    require!(
+        path.len() > 1,
+        "The path should point to a key within an account"
+    );
+    require!(
+        env::is_valid_account_id(path[0].as_bytes()),
+        "Invalid account id"
+    );
+    for key in &path[1..] {
+        require!(!key.is_empty(), "The path contains an empty key");
+        assert_key_valid(key);
+    }
+    path
+}
+
+impl Contract {
+    /// Returns the value at the given path, where the first key is an account ID.
+    pub fn internal_get_entry(&self, path: &[String]) -> Option<NodeValue> {
+        let mut node_value = NodeValue::Node(self.internal_get_account(&path[0])?.node_id);
+        for key in &path[1..] {
+            node_value = match node_value {
+                NodeValue::Node(node_id) => self.internal_unwrap_node(node_id).children.get(key)?,
+                _ => return None,
+            };
+        }
+        Some(node_value)
+    }
+
+    /// Converts the value or the subtree into the JSON data that `set` accepts. Deleted entries
+    /// are skipped. Nodes with the ordered or the recent index get the `:ordered` and `:recent`
+    /// markers, so the copies keep their indexes.
+    pub fn internal_entry_to_json(&self, node_value: NodeValue) -> Value {
+        match self.internal_resolve_node_value(node_value) {
+            NodeValue::Node(node_id) => {
+                let node = self.internal_unwrap_node(node_id);
+                let mut res = Map::new();
+                if node.ordered_keys.is_some() {
+                    res.insert(KEY_ORDERED.to_string(), Value::Bool(true));
+                }
+                if node.recent_keys.is_some() {
+                    res.insert(KEY_RECENT.to_string(), Value::Bool(true));
+                }
+                for (key, node_value) in node.children.iter() {
+                    if !node_value.is_deleted() {
+                        res.insert(key, self.internal_entry_to_json(node_value));
+                    }
+                }
+                Value::Object(res)
+            }
+            NodeValue::Value(value_at_height) => Value::String(value_at_height.value),
+            NodeValue::TypedValue(TypedValueAtHeight {
+                value: TypedValue::Binary(bytes),
+                ..
+            }) => {
+                let mut res = Map::new();
+                res.insert(
+                    KEY_BASE64.to_string(),
+                    near_sdk::serde_json::to_value(bytes).unwrap(),
+                );
+                Value::Object(res)
+            }
+            NodeValue::TypedValue(value_at_height) => value_at_height.value.to_json(),
+            NodeValue::DeletedEntry(_)
+            | NodeValue::DeletedEntryV1(_)
+            | NodeValue::SharedValue(_) => Value::Null,
+        }
+    }
+
+    /// Writes the given value to the path on behalf of the predecessor. The path should not exist
+    /// or should be deleted.
+    fn internal_set_new_entry(
+        &mut self,
+        path: &[String],
+        value: Value,
+        changes: &mut ChangedPaths,
+    ) {
+        require!(
+            self.internal_get_entry(path)
+                .map_or(true, |v| v.is_deleted()),
+            "The destination already exists"
+        );
+        let mut data = value;
+        for key in path[1..].iter().rev() {
+            let mut obj = Map::new();
+            obj.insert(key.clone(), data);
+            data = Value::Object(obj);
+        }
+        self.assert_keys_within_limits(&data);
+        self.internal_set_data(&path[0], &mut data, env::attached_deposit(), changes);
+    }
+
+    /// Walks from the account node to the parent of the last key of the path. Returns the parent
+    /// node and the approval of the writes of the predecessor to it. Missing nodes are created when `create`
+    /// is true, and their keys are added to `changed_keys`.
+    fn internal_walk_to_parent(
+        &mut self,
+        account: &Account,
+        path: &[String],
+        create: bool,
+        changed_keys: &mut Vec<(NodeId, String)>,
+    ) -> Option<(Node, WriteApproval)> {
+        let mut approval = WriteApproval::for_account(&path[0]);
+        let writable_nodes = if approval.is_approved() {
+            HashMap::new()
+        } else {
+            account.internal_get_writable_nodes()
+        };
+        let mut node = self.internal_unwrap_node(account.node_id);
+        for key in &path[1..path.len() - 1] {
+            approval = approval.for_node(node.node_id, &writable_nodes);
+            let next_node = match node.children.get(key) {
+                Some(NodeValue::Node(node_id)) => {
+                    node.touch_child(key);
+                    self.internal_unwrap_node(node_id)
+                }
+                node_value => {
+                    if !create {
+                        return None;
+                    }
+                    require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+                    changed_keys.push((node.node_id, key.clone()));
+                    let node_id = self.create_node_id();
+                    let prev_num_children = node.children.len();
+                    node.insert_child(key, &NodeValue::Node(node_id));
+                    self.limits.assert_children(&node, prev_num_children);
+                    Node::new(node_id, node_value)
+                }
+            };
+            self.internal_set_node(std::mem::replace(&mut node, next_node));
+        }
+        approval = approval.for_node(node.node_id, &writable_nodes);
+        Some((node, approval))
+    }
+
+    /// Removes the node and all its descendants from the storage. The IDs of the removed nodes are
+    /// added to `removed_node_ids`.
+    pub fn internal_remove_subtree(
+        &mut self,
+        node_id: NodeId,
+        blob_changes: &mut BlobChanges,
+        removed_node_ids: &mut Vec<NodeId>,
+    ) {
+        let mut node = self.internal_unwrap_node(node_id);
+        for node_value in node.children.values() {
+            match node_value {
+                NodeValue::Node(node_id) => {
+                    self.internal_remove_subtree(node_id, blob_changes, removed_node_ids)
+                }
+                NodeValue::SharedValue(v) => blob_changes.removed.push(v.hash.into()),
+                _ => {}
+            }
+        }
+        node.children.clear();
+        node.set_ordered(false);
+        node.set_recent(false);
+        self.nodes.remove(&node_id);
+        removed_node_ids.push(node_id);
+    }
+
+    /// Returns true if the subtree of the node has a path longer than `max_height` keys. Deleted
+    /// entries are skipped.
+    fn internal_subtree_exceeds_height(&self, node_id: NodeId, max_height: usize) -> bool {
+        let node = self.internal_unwrap_node(node_id);
+        node.children
+            .iter()
+            .any(|(key, node_value)| match node_value {
+                _ if key == EMPTY_KEY || node_value.is_deleted() => false,
+                NodeValue::Node(node_id) => {
+                    max_height == 0 || self.internal_subtree_exceeds_height(node_id, max_height - 1)
+                }
+                _ => max_height == 0,
+            })
+    }
+
+    /// Replaces the value at the given path with a deleted entry on behalf of the predecessor, and
+    /// removes the subtree from the storage. The parent is validated against the schemas the same
+    /// way as `set` validates a deletion.
+    fn internal_remove_entry(&mut self, path: &[String], changes: &mut ChangedPaths) {
+        let mut account = self.internal_unwrap_account(&path[0]);
+        let mut blob_changes = BlobChanges::default();
+        let mut removed_node_ids = vec![];
+        let mut changed_keys = vec![];
+        account.storage_tracker.start();
+        let (mut parent, approval) = self
+            .internal_walk_to_parent(&account, path, false, &mut changed_keys)
+            .expect("The source doesn't exist");
+        require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+        let key = path.last().unwrap();
+        changed_keys.push((parent.node_id, key.clone()));
+        let deleted_entry = NodeValue::new_deleted_entry(approval.author());
+        match parent.insert_child(key, &deleted_entry) {
+            Some(NodeValue::Node(node_id)) => {
+                self.internal_remove_subtree(node_id, &mut blob_changes, &mut removed_node_ids)
+            }
+            Some(NodeValue::SharedValue(v)) => blob_changes.removed.push(v.hash.into()),
+            _ => {}
+        }
+        self.internal_validate_deletion(&path[..path.len() - 1], &parent, key);
+        self.internal_set_node(parent);
+        account.storage_tracker.stop();
+        account.internal_remove_node_permissions(&removed_node_ids);
+        self.internal_set_account(account);
+        self.internal_apply_blob_changes(&path[0], blob_changes);
+        self.internal_append_journal(&path[0], changed_keys);
+        changes.deleted.push(PathsEventData::new(
+            &path[0],
+            vec![path.join(&SEPARATOR.to_string())],
+        ));
+    }
+
+    /// Moves the value at the `from` path to the `to` path within the same account without
+    /// rewriting the subtree. The nodes keep their IDs, so the write permissions granted on them
+    /// are moved as well.
+    fn internal_relink_entry(
+        &mut self,
+        from: &[String],
+        to: &[String],
+        changes: &mut ChangedPaths,
+    ) {
+        let mut account = self.internal_unwrap_account_or_create(&to[0], env::attached_deposit());
+        let max_depth = self.limits.max_depth as usize;
+        // Moving the subtree deeper can make its paths exceed the maximum depth.
+        let exceeds_max_depth = to.len() > max_depth
+            || (to.len() > from.len()
+                && match self.internal_get_entry(from) {
+                    Some(NodeValue::Node(node_id)) => {
+                        self.internal_subtree_exceeds_height(node_id, max_depth - to.len())
+                    }
+                    _ => false,
+                });
+        require!(
+            !exceeds_max_depth,
+            format!("The path exceeds the maximum depth {}", max_depth)
+        );
+        let mut changed_keys = vec![];
+        account.storage_tracker.start();
+        let (mut parent, approval) = self
+            .internal_walk_to_parent(&account, from, false, &mut changed_keys)
+            .expect("The source doesn't exist");
+        require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+        changed_keys.push((parent.node_id, from.last().unwrap().clone()));
+        let deleted_entry = NodeValue::new_deleted_entry(approval.author());
+        let node_value = parent
+            .insert_child(from.last().unwrap(), &deleted_entry)
+            .expect("The source doesn't exist");
+        self.internal_set_node(parent);
+
+        let (mut parent, approval) = self
+            .internal_walk_to_parent(&account, to, true, &mut changed_keys)
+            .unwrap();
+        require!(approval.is_approved(), ERR_PERMISSION_DENIED);
+        changed_keys.push((parent.node_id, to.last().unwrap().clone()));
+        let prev_num_children = parent.children.len();
+        parent.insert_child(to.last().unwrap(), &node_value.into_current_height());
+        require!(
+            parent.children.len() <= prev_num_children
+                || parent.children.len() <= self.limits.max_children as u64,
+            format!(
+                "The node exceeds the maximum number of children {}",
+                self.limits.max_children
+            )
+        );
+        self.internal_set_node(parent);
+        account.storage_tracker.stop();
+        self.internal_set_account(account);
+        self.internal_append_journal(&to[0], changed_keys);
+        changes.deleted.push(PathsEventData::new(
+            &from[0],
+            vec![from.join(&SEPARATOR.to_string())],
+        ));
+        changes.written.push(PathsEventData::new(
+            &to[0],
+            vec![to.join(&SEPARATOR.to_string())],
+        ));
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Copies the value or the subtree at the `from` path to the `to` path, e.g.
+    /// `copy({from: "alex.near/widget/Template", to: "alex.near/app/widget/Main"})`.
+    /// The destination should not exist or should be deleted. The copy is written the same way as
+    /// `set` writes the data, so the predecessor should have the write permission for the
+    /// destination and the destination account covers the storage. The attached deposit is added
+    /// to the destination account. Deleted entries are not copied.
+    #[payable]
+    pub fn copy(&mut self, from: String, to: String) {
+        self.assert_live();
+        let from = parse_path(&from);
+        let to = parse_path(&to);
+        let value = self.internal_entry_to_json(
+            self.internal_get_entry(&from)
+                .filter(|v| !v.is_deleted())
+                .expect("The source doesn't exist"),
+        );
+        let mut changes = ChangedPaths::default();
+        self.internal_set_new_entry(&to, value, &mut changes);
+        self.internal_notify_subscribers(&changes);
+        changes.emit();
+    }
+
+    /// Moves the value or the subtree at the `from` path to the `to` path, e.g.
+    /// `rename({from: "alex.near/widget/Old", to: "alex.near/widget/New"})`.
+    /// The predecessor should have the write permission for both paths, and the source is replaced
+    /// with a deleted entry. The destination should not exist or should be deleted.
+    ///
+    /// Within the same account the subtree is relinked without rewriting it. When the paths belong
+    /// to different accounts, or the account has schemas, the subtree is copied the same way as
+    /// `copy` does, and then removed from the source account.
+    #[payable]
+    pub fn rename(&mut self, from: String, to: String) {
+        self.assert_live();
+        let from = parse_path(&from);
+        let to = parse_path(&to);
+        require!(
+            !to.starts_with(&from),
+            "The destination can't be within the source"
+        );
+        let source = self
+            .internal_get_entry(&from)
+            .filter(|v| !v.is_deleted())
+            .expect("The source doesn't exist");
+        let mut changes = ChangedPaths::default();
+        if from[0] == to[0] && self.internal_get_account_schemas(&to[0]).is_empty() {
+            require!(
+                self.internal_get_entry(&to)
+                    .map_or(true, |v| v.is_deleted()),
+                "The destination already exists"
+            );
+            self.internal_relink_entry(&from, &to, &mut changes);
+        } else {
+            let value = self.internal_entry_to_json(source);
+            self.internal_set_new_entry(&to, value, &mut changes);
+            self.internal_remove_entry(&from, &mut changes);
+        }
+        self.internal_notify_subscribers(&changes);
+        changes.emit();
+    }
+}
@@ -6,8 +6,8 @@ impl Contract {
     #[private]
     #[init(ignore_state)]
     pub fn migrate_state() -> Self {
-        let old_contract: Self = env::state_read().expect("Old state doesn't exist");
-        old_contract
+        let old_contract: ContractV0 = env::state_read().expect("Old state doesn't exist");
+        old_contract.into()
     }
 
     #[private]
@@ -32,6 +32,30 @@ impl Contract {
         }
     }
 
+    /// Initializes the blobs of the shared values with their reference counts. The storage is
+    /// expected to be covered by the `used_bytes` of the accounts.
+    #[private]
+    pub fn genesis_init_blobs(&mut self, blobs: Vec<GenesisBlob>) {
+        self.assert_genesis();
+        for blob in blobs {
+            let hash: CryptoHash = blob.hash.into();
+            let mut ref_count = 0;
+            for (account_id, count) in blob.ref_counts {
+                self.blob_refs.insert(&(hash, account_id), &count);
+                ref_count += count;
+            }
+            self.blob_ref_counts.insert(&hash, &ref_count);
+            self.blobs.insert(
+                &hash,
+                &Blob {
+                    value: blob.value,
+                    owner_id: blob.owner_id,
+                }
+                .into(),
+            );
+        }
+    }
+
     #[private]
     pub fn genesis_init_accounts(&mut self, accounts: Vec<(AccountId, PartialAccount)>) {
         self.assert_genesis();
@@ -72,7 +96,7 @@ impl Contract {
 
 fn populate_node(node: &mut Node, partial_node: PartialNode) {
     for (key, value) in partial_node.children {
-        node.children.insert(&key, &value.into_current_height());
+        node.insert_child(&key, &value.into_current_height());
     }
 }
 
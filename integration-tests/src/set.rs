@@ -17,6 +17,36 @@ async fn main() -> Result<()> {
     test_set_method().await?;
     test_set_method_and_refund().await?;
     test_set_method_and_refund_with_existing_deposit().await?;
+    test_set_typed_values().await?;
+    test_set_binary_value().await?;
+    test_set_compressed_value().await?;
+    test_shared_values().await?;
+    test_set_replace_subtree().await?;
+    test_set_paths().await?;
+    test_get_flat().await?;
+    test_get_max_depth().await?;
+    test_get_with_size().await?;
+    test_get_with_timestamp().await?;
+    test_count_with_deleted_children().await?;
+    test_schema_with_star_pattern().await?;
+    test_rename_validates_source_schema().await?;
+    test_canonical_keys().await?;
+    test_copy_and_rename().await?;
+    test_rename_depth_limit().await?;
+    test_get_with_author().await?;
+    test_get_incomplete_with_cursor().await?;
+    test_join_within_gas_budget().await?;
+    test_exclude_subtree().await?;
+    test_get_recent_with_index().await?;
+    test_get_range().await?;
+    test_set_emits_events().await?;
+    test_replace_emits_compacted_events().await?;
+    test_get_changes().await?;
+    test_journal_is_charged_to_accounts().await?;
+    test_get_nodes_since().await?;
+    test_subscription_is_charged_for_matching_writes().await?;
+    test_third_party_cannot_drain_subscription().await?;
+    test_set_limits().await?;
     Ok(())
 }
 
@@ -172,6 +202,2021 @@ async fn test_set_method_and_refund_with_existing_deposit() -> Result<()> {
     Ok(())
 }
 
+/// Numbers, booleans and arrays are returned with their original JSON type.
+async fn test_set_typed_values() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let settings = json!({
+        "dark_mode": true,
+        "font_size": 14,
+        "ratio": 1.5,
+        "languages": ["en", { "fr": "partial" }],
+        "theme": "dark",
+    });
+    let args = json!({
+        "data": {
+            &user_id: {
+                "settings": settings,
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/settings/*")] }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(result[&user_id]["settings"], settings);
+
+    // `get_node` returns strings in the `0.12.0` format and other values with their type.
+    let node_id = get_account_node_id(&contract, &user).await?;
+    let settings_node = get_node_child(&contract, &user, node_id, "settings", false).await?;
+    let settings_node_id = settings_node["Node"].as_u64().unwrap();
+    let theme = get_node_child(&contract, &user, settings_node_id, "theme", false).await?;
+    assert_eq!(theme["Value"]["value"], json!("dark"));
+    let font_size = get_node_child(&contract, &user, settings_node_id, "font_size", false).await?;
+    assert_eq!(font_size["TypedValue"]["value"]["Number"], json!("14"));
+
+    Ok(())
+}
+
+/// Binary values are accepted as `{":base64": ...}` and returned as base64 strings.
+async fn test_set_binary_value() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let thumbnail = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAY=";
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "thumbnail": { ":base64": thumbnail },
+                },
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/profile/thumbnail")] }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(result[&user_id]["profile"]["thumbnail"], thumbnail);
+
+    Ok(())
+}
+
+/// Large strings are stored compressed, but returned as the original string by `get`, and by
+/// `get_node` and `get_blob` with `decompress`.
+async fn test_set_compressed_value() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let about = "Hello world! ".repeat(100);
+    let args = json!({
+        "data": {
+            &user_id: {
+                "about": about,
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/about")] }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(result[&user_id]["about"], json!(about));
+
+    let node_id = get_account_node_id(&contract, &user).await?;
+    let stored = get_node_child(&contract, &user, node_id, "about", false).await?;
+    let compressed = stored["TypedValue"]["value"]["CompressedString"]
+        .as_str()
+        .unwrap();
+    assert!(compressed.len() < about.len());
+
+    let decompressed = get_node_child(&contract, &user, node_id, "about", true).await?;
+    assert_eq!(decompressed["Value"]["value"], json!(about));
+
+    // A large compressed value is stored in a shared blob, which is decompressed by `get_blob`.
+    let bio: String = (0..400).map(|i| format!("Hello world! {i}\n")).collect();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "bio": bio } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let shared_value = get_node_child(&contract, &user, node_id, "bio", true).await?;
+    let hash = shared_value["SharedValue"]["hash"].clone();
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<Value>()?;
+    assert!(blob["value"]["CompressedString"].is_string());
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash, "decompress": true }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(blob["value"]["String"], json!(bio));
+
+    Ok(())
+}
+
+/// Large values are stored once in a shared blob. After the owner removes its references, the blob
+/// storage moves to the next referrer that changes its references, and the blob is removed with
+/// the last reference.
+async fn test_shared_values() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let other_user = worker.dev_create_account().await?;
+
+    // The journal is disabled, so the used bytes only change by the values and the blob.
+    contract
+        .call("set_journal_max_entries")
+        .args_json(json!({ "max_entries": 0 }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let value = json!((0..400).collect::<Vec<u32>>());
+    for account in [&user, &other_user] {
+        account
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": { account.id(): { "data": value } } }))
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    let user_node_id = get_account_node_id(&contract, &user).await?;
+    let other_node_id = get_account_node_id(&contract, &other_user).await?;
+    let shared_value = get_node_child(&contract, &user, user_node_id, "data", false).await?;
+    let hash = shared_value["SharedValue"]["hash"].clone();
+    let other_shared_value = get_node_child(&contract, &user, other_node_id, "data", false).await?;
+    assert_eq!(other_shared_value["SharedValue"]["hash"], hash);
+
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(blob["owner_id"], json!(user.id()));
+    let ref_count = user
+        .view(contract.id(), "get_blob_ref_count")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<u32>()?;
+    assert_eq!(ref_count, 2);
+
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { other_user.id(): { "copy": value } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { user.id(): { "data": null } } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The first writer keeps covering the blob until another referrer changes its references.
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(blob["owner_id"], json!(user.id()));
+
+    let used_bytes = get_account_used_bytes(&contract, &user).await?;
+    let other_used_bytes = get_account_used_bytes(&contract, &other_user).await?;
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { other_user.id(): { "copy": null } } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The storage of the blob moves from the first writer to the other referrer. The other
+    // referrer already covers its reference count, and the deleted entry is 32 bytes shorter than
+    // the shared value.
+    let released_bytes = used_bytes - get_account_used_bytes(&contract, &user).await?;
+    let other_added_bytes =
+        get_account_used_bytes(&contract, &other_user).await? + 32 - other_used_bytes;
+    assert!(released_bytes > 1024);
+    assert_eq!(other_added_bytes, released_bytes);
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(blob["owner_id"], json!(other_user.id()));
+
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { other_user.id(): { "data": null } } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let blob = user
+        .view(contract.id(), "get_blob")
+        .args_json(json!({ "hash": hash }))
+        .await?
+        .json::<Value>()?;
+    assert!(blob.is_null());
+
+    Ok(())
+}
+
+async fn test_set_replace_subtree() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "name": "Alex",
+                    "about": "Hello",
+                    "links": { "github": "alex" },
+                },
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    ":replace": true,
+                    "name": "Alex",
+                },
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/profile/**")] }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(result, json!({ &user_id: { "profile": { "name": "Alex" } } }));
+
+    Ok(())
+}
+
+/// `set_paths` writes the values at the given paths, the last value wins, and `null` deletes.
+async fn test_set_paths() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    user.call(contract.id(), "set_paths")
+        .args_json(json!({ "entries": [
+            [format!("{user_id}/profile/name"), "Alex"],
+            [format!("{user_id}/profile/image/url"), "https://example.com"],
+            [format!("{user_id}/profile/name"), "Alexander"],
+        ] }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let keys = json!([format!("{user_id}/profile/**")]);
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": keys }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": {
+            "name": "Alexander",
+            "image": { "url": "https://example.com" },
+        } } })
+    );
+
+    user.call(contract.id(), "set_paths")
+        .args_json(json!({ "entries": [[format!("{user_id}/profile/image/url"), null]] }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": keys }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": { "name": "Alexander" } } })
+    );
+
+    Ok(())
+}
+
+/// With the `flat` option `get` and `keys` return path records, and the value of a node is
+/// returned at the path of the node.
+async fn test_get_flat() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "widget": {
+                    "Main": {
+                        "": "return <div/>;",
+                        "metadata": { "name": "Main" },
+                    },
+                },
+            }
+        }
+    });
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let records = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/widget/**")],
+            "options": { "flat": true },
+        }))
+        .await?
+        .json::<Vec<Value>>()?;
+    let paths: Vec<(String, Value)> = records
+        .iter()
+        .map(|record| {
+            (
+                record["path"].as_str().unwrap().to_string(),
+                record["value"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            (format!("{user_id}/widget/Main"), json!("return <div/>;")),
+            (
+                format!("{user_id}/widget/Main/metadata/name"),
+                json!("Main")
+            ),
+        ]
+    );
+    assert!(records[0]["node_id"].is_u64());
+    assert!(records[1]["node_id"].is_null());
+
+    let records = user
+        .view(contract.id(), "keys")
+        .args_json(json!({
+            "keys": [format!("{user_id}/widget/*")],
+            "options": { "flat": true },
+        }))
+        .await?
+        .json::<Vec<Value>>()?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["path"], json!(format!("{user_id}/widget/Main")));
+    assert!(records[0]["node_id"].is_u64());
+    assert!(records[0].get("value").is_none());
+
+    Ok(())
+}
+
+/// The nodes at the last level of `max_depth` are returned truncated with their own value and the
+/// number of their children.
+async fn test_get_max_depth() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "name": "Alex",
+                    "image": {
+                        "": "avatar",
+                        "url": "https://example.com",
+                        "alt": "Alex",
+                    },
+                },
+            }
+        }
+    });
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/profile/**")],
+            "options": { "max_depth": 1 },
+        }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": {
+            "name": "Alex",
+            "image": { ":truncated": true, ":children": 3, "": "avatar" },
+        } } })
+    );
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/profile/**")],
+            "options": { "max_depth": 2 },
+        }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": {
+            "name": "Alex",
+            "image": { "": "avatar", "url": "https://example.com", "alt": "Alex" },
+        } } })
+    );
+
+    Ok(())
+}
+
+/// The size of a node is the sum of the sizes of the keys and the values in its subtree, and it's
+/// the same with and without `max_depth`.
+async fn test_get_with_size() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": { "name": "Alex", "about": "Hello" },
+                "widget": { "Main": { "": "return <div/>;", "metadata": { "name": "Main" } } },
+            }
+        }
+    });
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/**")],
+            "options": { "with_size": true },
+        }))
+        .await?
+        .json::<Value>()?;
+    let size = |value: &Value| value[":size"].as_u64().unwrap();
+    let account = &result[&user_id];
+    let profile = &account["profile"];
+    let main = &account["widget"]["Main"];
+    assert_eq!(
+        size(profile),
+        "name".len() as u64
+            + size(&profile["name"])
+            + "about".len() as u64
+            + size(&profile["about"])
+    );
+    assert_eq!(
+        size(main),
+        size(&main[""]) + "metadata".len() as u64 + size(&main["metadata"])
+    );
+    assert_eq!(size(&account["widget"]), "Main".len() as u64 + size(main));
+    assert_eq!(
+        size(account),
+        "profile".len() as u64 + size(profile) + "widget".len() as u64 + size(&account["widget"])
+    );
+
+    let truncated = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/**")],
+            "options": { "with_size": true, "max_depth": 1 },
+        }))
+        .await?
+        .json::<Value>()?;
+    let truncated = &truncated[&user_id];
+    assert_eq!(size(truncated), size(account));
+    assert_eq!(size(&truncated["profile"]), size(profile));
+    assert_eq!(size(&truncated["widget"]), size(&account["widget"]));
+
+    Ok(())
+}
+
+/// Values and nodes return the timestamp of the block when they were last updated, both with the
+/// `with_timestamp` option of `get` and the `Timestamp` return type of `keys`.
+async fn test_get_with_timestamp() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let mut timestamps = vec![];
+    for name in ["Alex", "Alice"] {
+        user.call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "profile": { "name": name } } } }))
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result()?;
+
+        let result = user
+            .view(contract.id(), "get")
+            .args_json(json!({
+                "keys": [format!("{user_id}/profile/**")],
+                "options": { "with_timestamp": true },
+            }))
+            .await?
+            .json::<Value>()?;
+        let profile = &result[&user_id]["profile"];
+        assert_eq!(profile["name"][""], json!(name));
+        let timestamp = profile["name"][":timestamp"].clone();
+        assert!(timestamp.as_str().unwrap().parse::<u64>()? > 0);
+        // The node is updated in the same block as its value.
+        assert_eq!(profile[":timestamp"], timestamp);
+
+        let result = user
+            .view(contract.id(), "keys")
+            .args_json(json!({
+                "keys": [format!("{user_id}/profile/*")],
+                "options": { "return_type": "Timestamp" },
+            }))
+            .await?
+            .json::<Value>()?;
+        assert_eq!(
+            result,
+            json!({ &user_id: { "profile": { "name": timestamp } } })
+        );
+        timestamps.push(timestamp.as_str().unwrap().parse::<u64>()?);
+    }
+    assert!(timestamps[1] > timestamps[0]);
+
+    Ok(())
+}
+
+/// `count` skips the deleted children unless `return_deleted` is set.
+async fn test_count_with_deleted_children() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let follow = json!({ "root.near": "", "bob.near": "", "carol.near": "" });
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "graph": { "follow": follow } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "graph": { "follow": { "bob.near": null } } } } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let keys = json!([
+        format!("{user_id}/graph/follow/*"),
+        format!("{user_id}/graph/follow/bob.near"),
+    ]);
+    let counts = user
+        .view(contract.id(), "count")
+        .args_json(json!({ "keys": keys }))
+        .await?
+        .json::<Vec<u64>>()?;
+    assert_eq!(counts, vec![2, 0]);
+
+    let counts = user
+        .view(contract.id(), "count")
+        .args_json(json!({ "keys": keys, "options": { "return_deleted": true } }))
+        .await?
+        .json::<Vec<u64>>()?;
+    assert_eq!(counts, vec![3, 1]);
+
+    Ok(())
+}
+
+/// A schema with `*` in the pattern validates all matching nodes.
+async fn test_schema_with_star_pattern() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    user.call(contract.id(), "set_schema")
+        .args_json(json!({
+            "pattern": format!("{user_id}/widget/*/metadata"),
+            "schema": {
+                "allowed_keys": ["name"],
+                "value_pattern": "^[A-Z]",
+            },
+        }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for (widget, name) in [("Main", "Main"), ("Feed", "Feed")] {
+        user.call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "widget": { widget: {
+                "": "return <div/>;",
+                "metadata": { "name": name },
+            } } } } }))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    let rejected = [
+        // The value doesn't match the value pattern.
+        json!({ "name": "main" }),
+        // The key is not allowed.
+        json!({ "name": "Main", "tags": "app" }),
+    ];
+    for metadata in rejected {
+        let result = user
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "widget": { "Main": {
+                "metadata": metadata,
+            } } } } }))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+
+    Ok(())
+}
+
+/// `rename` validates the parent of the source against the schemas, the same way as `set`
+/// validates a deletion.
+async fn test_rename_validates_source_schema() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let other_user = worker.dev_create_account().await?;
+
+    let user_id = user.id().to_string();
+    let other_user_id = other_user.id().to_string();
+    user.call(contract.id(), "set_schema")
+        .args_json(json!({
+            "pattern": format!("{user_id}/profile"),
+            "schema": { "required_keys": ["name"] },
+        }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "profile": { "name": "Alex", "bio": "Hi" } } } }))
+        .transact()
+        .await?
+        .into_result()?;
+    other_user
+        .call(contract.id(), "grant_write_permission")
+        .args_json(json!({
+            "predecessor_id": user_id,
+            "keys": [format!("{other_user_id}/profile")],
+        }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for to in [
+        format!("{user_id}/about/name"),
+        format!("{other_user_id}/profile/name"),
+    ] {
+        let result = user
+            .call(contract.id(), "rename")
+            .args_json(json!({ "from": format!("{user_id}/profile/name"), "to": to }))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+
+    // Keys that are not required can still be moved.
+    user.call(contract.id(), "rename")
+        .args_json(json!({
+            "from": format!("{user_id}/profile/bio"),
+            "to": format!("{user_id}/about/bio"),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/**")] }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": { "name": "Alex" }, "about": { "bio": "Hi" } } })
+    );
+
+    Ok(())
+}
+
+/// Only canonical keys can be written, and the patterns of `get` are canonicalized.
+async fn test_canonical_keys() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let canonical_key = user
+        .view(contract.id(), "get_canonical_key")
+        .args_json(json!({ "key": "café" }))
+        .await?
+        .json::<String>()?;
+    assert_eq!(canonical_key, "caf%C3%A9");
+
+    // The decomposed form is normalized to NFC.
+    let canonical_key = user
+        .view(contract.id(), "get_canonical_key")
+        .args_json(json!({ "key": "cafe\u{301}" }))
+        .await?
+        .json::<String>()?;
+    assert_eq!(canonical_key, "caf%C3%A9");
+
+    // The maximum length applies to the decoded key: 128 * 2 bytes take 768 escaped characters.
+    let long_key = "%C3%A9".repeat(128);
+    let user_id = user.id().to_string();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "tags": { "caf%C3%A9": "", &long_key: "" } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for key in ["café", "caf%c3%a9", "cafe%CC%81"] {
+        let result = user
+            .view(contract.id(), "get")
+            .args_json(json!({ "keys": [format!("{user_id}/tags/{key}")] }))
+            .await?
+            .json::<Value>()?;
+        assert_eq!(result, json!({ &user_id: { "tags": { "caf%C3%A9": "" } } }));
+    }
+    let long_key_too_long = format!("{long_key}a");
+
+    let rejected = [
+        // Not escaped.
+        "café",
+        "#near",
+        // Lowercase hex digits.
+        "caf%c3%a9",
+        // An unnecessary escape.
+        "%41",
+        // An escaped zero-width space.
+        "a%E2%80%8Bb",
+        // Not in NFC.
+        "cafe%CC%81",
+        // The decoded key is longer than 256 bytes.
+        long_key_too_long.as_str(),
+    ];
+    for key in rejected {
+        let result = user
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "tags": { key: "" } } } }))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+
+    Ok(())
+}
+
+/// `copy` and `rename` within an account and to another account. The permissions granted on the
+/// removed nodes are removed.
+async fn test_copy_and_rename() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let other_user = worker.dev_create_account().await?;
+
+    let user_id = user.id().to_string();
+    let other_user_id = other_user.id().to_string();
+    let widget = json!({ "": "return <div/>;", "metadata": { "name": "Template" } });
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "widget": { "Template": widget } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    user.call(contract.id(), "copy")
+        .args_json(json!({
+            "from": format!("{user_id}/widget/Template"),
+            "to": format!("{user_id}/widget/Main"),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "rename")
+        .args_json(json!({
+            "from": format!("{user_id}/widget/Template"),
+            "to": format!("{user_id}/widget/Feed"),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/widget/**")] }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "widget": { "Main": widget, "Feed": widget } } })
+    );
+
+    // The copies keep the ordered and the recent indexes, which take additional storage.
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: {
+            "indexed": { ":ordered": true, ":recent": true, "a": "1", "b": "2" },
+            "plain": { "a": "1", "b": "2" },
+        } } }))
+        .transact()
+        .await?
+        .into_result()?;
+    let mut copied_bytes = vec![];
+    for key in ["indexed", "plain"] {
+        let used_bytes = get_account_used_bytes(&contract, &user).await?;
+        user.call(contract.id(), "copy")
+            .args_json(json!({
+                "from": format!("{user_id}/{key}"),
+                "to": format!("{user_id}/{key}_copy"),
+            }))
+            .transact()
+            .await?
+            .into_result()?;
+        copied_bytes.push(get_account_used_bytes(&contract, &user).await? - used_bytes);
+    }
+    assert!(copied_bytes[0] > copied_bytes[1]);
+
+    // Moving to another account requires a permission from that account.
+    other_user
+        .call(contract.id(), "grant_write_permission")
+        .args_json(json!({
+            "predecessor_id": user_id,
+            "keys": [format!("{other_user_id}/widget")],
+        }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "rename")
+        .args_json(json!({
+            "from": format!("{user_id}/widget/Feed"),
+            "to": format!("{other_user_id}/widget/Feed"),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [
+            format!("{user_id}/widget/*"),
+            format!("{other_user_id}/widget/**"),
+        ] }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({
+            &user_id: { "widget": { "Main": "return <div/>;" } },
+            &other_user_id: { "widget": { "Feed": widget } },
+        })
+    );
+
+    // Replacing the widgets removes the node with the granted permission.
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { &other_user_id: { ":replace": true } } }))
+        .transact()
+        .await?
+        .into_result()?;
+    let permissions = user
+        .view(contract.id(), "debug_get_permissions")
+        .args_json(json!({ "account_id": other_user_id }))
+        .await?
+        .json::<Vec<Value>>()?;
+    assert!(permissions.is_empty());
+
+    Ok(())
+}
+
+/// `rename` within an account is rejected if the moved subtree would exceed the maximum depth.
+async fn test_rename_depth_limit() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    contract
+        .call("set_limits")
+        .args_json(json!({ "limits": {
+            "max_value_length": 1024,
+            "max_depth": 4,
+            "max_children": 100,
+            "max_keys_per_call": 100,
+        } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let user_id = user.id().to_string();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "a": { "b": { "c": "1" } } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .call(contract.id(), "rename")
+        .args_json(json!({
+            "from": format!("{user_id}/a"),
+            "to": format!("{user_id}/x/a"),
+        }))
+        .transact()
+        .await?
+        .into_result();
+    assert!(result.is_err());
+
+    user.call(contract.id(), "rename")
+        .args_json(json!({
+            "from": format!("{user_id}/a/b"),
+            "to": format!("{user_id}/x/b"),
+        }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": [format!("{user_id}/x/**")] }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(result, json!({ &user_id: { "x": { "b": { "c": "1" } } } }));
+
+    Ok(())
+}
+
+/// The author of a value is the permission key that was granted the permission on the path of the
+/// value, even if the predecessor account and the signer public key both have permissions.
+async fn test_get_with_author() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let other_user = worker.dev_create_account().await?;
+
+    let user_id = user.id().to_string();
+    let other_user_id = other_user.id().to_string();
+    let public_key = user.secret_key().public_key().to_string();
+    let grants = [
+        json!({ "predecessor_id": user_id, "keys": [format!("{other_user_id}/widget")] }),
+        json!({ "public_key": public_key, "keys": [format!("{other_user_id}/profile")] }),
+    ];
+    for args in grants {
+        other_user
+            .call(contract.id(), "grant_write_permission")
+            .args_json(args)
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &other_user_id: {
+            "widget": { "Main": "return <div/>;" },
+            "profile": { "name": "Bob" },
+        } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { &other_user_id: { "profile": { "about": "Hi" } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{other_user_id}/**")],
+            "options": { "with_author": true },
+        }))
+        .await?
+        .json::<Value>()?;
+    let data = &result[&other_user_id];
+    assert_eq!(
+        data["widget"]["Main"][":author"],
+        json!({ "AccountId": user_id })
+    );
+    assert_eq!(
+        data["profile"]["name"][":author"],
+        json!({ "SignerPublicKey": public_key })
+    );
+    // The values written by the account itself don't have the author.
+    assert_eq!(data["profile"]["about"], json!({ "": "Hi" }));
+
+    Ok(())
+}
+
+async fn test_get_incomplete_with_cursor() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "name": "Alex",
+                    "about": "Hello",
+                },
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let keys = json!([format!("{user_id}/profile/**")]);
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": keys, "options": { "gas_budget": "1" } }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(result, json!({ ":incomplete": true, ":cursor": "0" }));
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": keys, "options": { "cursor": "0" } }))
+        .await?
+        .json::<Value>()?;
+
+    assert_eq!(
+        result,
+        json!({ &user_id: { "profile": { "name": "Alex", "about": "Hello" } } })
+    );
+
+    Ok(())
+}
+
+/// Range patterns match the keys in the lexicographic order for both ordered and unordered nodes,
+/// and the deleted entries are not counted towards the limit. The order can't be added to a node
+/// with keys.
+async fn test_get_range() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let days = json!({
+        "2024-01-20": "3",
+        "2024-01-15": "2",
+        "2024-02-01": "4",
+        "2024-01-10": "1",
+    });
+    let mut post = days.clone();
+    post[":ordered"] = json!(true);
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "post": post, "note": days } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: {
+            "post": { "2024-01-10": null },
+            "note": { "2024-01-10": null },
+        } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    for key in ["post", "note"] {
+        let records = user
+            .view(contract.id(), "get")
+            .args_json(json!({
+                "keys": [format!("{user_id}/{key}/[2024-01..2024-02]")],
+                "options": { "limit": 2, "flat": true },
+            }))
+            .await?
+            .json::<Vec<Value>>()?;
+        let paths: Vec<(String, Value)> = records
+            .iter()
+            .map(|record| {
+                (
+                    record["path"].as_str().unwrap().to_string(),
+                    record["value"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (format!("{user_id}/{key}/2024-01-15"), json!("2")),
+                (format!("{user_id}/{key}/2024-01-20"), json!("3")),
+            ]
+        );
+
+        let result = user
+            .view(contract.id(), "keys")
+            .args_json(json!({
+                "keys": [format!("{user_id}/{key}/[2024-01-15..]")],
+                "options": { "limit": 1 },
+            }))
+            .await?
+            .json::<Value>()?;
+        assert_eq!(result, json!({ &user_id: { key: { "2024-01-15": true } } }));
+    }
+
+    // The indexes can only be added to the nodes without keys.
+    for marker in [":ordered", ":recent"] {
+        let result = user
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "note": { marker: true } } } }))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+
+    Ok(())
+}
+
+/// The inner pattern of a join is evaluated within the gas budget of the call.
+async fn test_join_within_gas_budget() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "graph": { "follow": { &user_id: "" } },
+                "profile": { "name": "Alex" },
+            }
+        }
+    });
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let keys = json!([format!("{{{user_id}/graph/follow/*}}/profile/name")]);
+    for options in [
+        json!({ "gas_budget": "1" }),
+        json!({ "gas_budget": "1", "order_by": "BlockHeight", "limit": 10 }),
+    ] {
+        let result = user
+            .view(contract.id(), "get")
+            .args_json(json!({ "keys": keys, "options": options }))
+            .await?
+            .json::<Value>()?;
+        assert_eq!(result, json!({ ":incomplete": true }));
+    }
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({ "keys": keys }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(result, json!({ &user_id: { "profile": { "name": "Alex" } } }));
+
+    Ok(())
+}
+
+/// An exclusion pattern ending with `**` skips the whole subtree, including the value of the node
+/// itself, in `get`, `keys` and `count`.
+async fn test_exclude_subtree() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": { "name": "Alex" },
+                "widget": {
+                    "": "widgets",
+                    "Main": {
+                        "": "return <div/>;",
+                        "metadata": { "name": "Main" },
+                    },
+                },
+            }
+        }
+    });
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/**")],
+            "options": { "exclude": [format!("{user_id}/widget/**")] },
+        }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(result, json!({ &user_id: { "profile": { "name": "Alex" } } }));
+
+    let result = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/widget/**")],
+            "options": { "exclude": ["*/widget/*/metadata/**"] },
+        }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(
+        result,
+        json!({ &user_id: { "widget": { "": "widgets", "Main": { "": "return <div/>;" } } } })
+    );
+
+    let result = user
+        .view(contract.id(), "keys")
+        .args_json(json!({
+            "keys": [format!("{user_id}/*")],
+            "options": { "exclude": [format!("{user_id}/widget/**")] },
+        }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(result, json!({ &user_id: { "profile": true } }));
+
+    let result = user
+        .view(contract.id(), "count")
+        .args_json(json!({
+            "keys": [format!("{user_id}/*"), format!("{user_id}/widget/*")],
+            "options": { "exclude": [format!("{user_id}/widget/**")] },
+        }))
+        .await?
+        .json::<Vec<u64>>()?;
+    assert_eq!(result, vec![1, 0]);
+
+    Ok(())
+}
+
+/// The children of a node written with `:recent` are returned in the order of their last change,
+/// including the changes deep in the subtrees of the child nodes.
+async fn test_get_recent_with_index() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let writes = [
+        json!({ ":recent": true, "a": "1", "b": { "text": "2" } }),
+        json!({ "c": "3" }),
+        json!({ "b": { "text": "4" } }),
+        json!({ "d": { "text": "5" } }),
+        json!({ "a": null }),
+    ];
+    for comment in writes {
+        user.call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "comment": comment } } }))
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    let records = user
+        .view(contract.id(), "keys")
+        .args_json(json!({
+            "keys": [format!("{user_id}/comment/*")],
+            "options": { "order_by": "BlockHeight", "limit": 3, "flat": true },
+        }))
+        .await?
+        .json::<Vec<Value>>()?;
+    let paths: Vec<String> = records
+        .iter()
+        .map(|record| record["path"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            format!("{user_id}/comment/d"),
+            format!("{user_id}/comment/b"),
+            format!("{user_id}/comment/c"),
+        ]
+    );
+
+    let records = user
+        .view(contract.id(), "get")
+        .args_json(json!({
+            "keys": [format!("{user_id}/comment/**")],
+            "options": {
+                "order_by": "BlockHeight",
+                "limit": 3,
+                "flat": true,
+                "return_deleted": true,
+            },
+        }))
+        .await?
+        .json::<Vec<Value>>()?;
+    let paths: Vec<(String, Value)> = records
+        .iter()
+        .map(|record| {
+            (
+                record["path"].as_str().unwrap().to_string(),
+                record["value"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            (format!("{user_id}/comment/a"), Value::Null),
+            (format!("{user_id}/comment/d/text"), json!("5")),
+            (format!("{user_id}/comment/b/text"), json!("4")),
+        ]
+    );
+
+    // Removing the index keeps the order, which is then found by reading all the keys.
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "comment": { ":recent": false } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    let records = user
+        .view(contract.id(), "keys")
+        .args_json(json!({
+            "keys": [format!("{user_id}/comment/*")],
+            "options": { "order_by": "BlockHeight", "limit": 1, "flat": true },
+        }))
+        .await?
+        .json::<Vec<Value>>()?;
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["path"], json!(format!("{user_id}/comment/d")));
+
+    // The accounts are not indexed by recency, so the patterns should start with an account ID.
+    for key in ["*/comment/*", "**", "[a..z]/comment/*"] {
+        let result = user
+            .view(contract.id(), "keys")
+            .args_json(json!({
+                "keys": [key],
+                "options": { "order_by": "BlockHeight", "limit": 1 },
+            }))
+            .await;
+        assert!(result.is_err());
+    }
+
+    Ok(())
+}
+
+async fn test_set_emits_events() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "name": "Alex",
+                    "about": null,
+                },
+            }
+        }
+    });
+
+    let outcome = user
+        .call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let events: Vec<Value> = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|log| serde_json::from_str(log))
+        .collect::<Result<_, _>>()?;
+    let event_names: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(event_names, vec!["storage_deposit", "set", "delete"]);
+    assert_eq!(events[1]["standard"], "social");
+    assert_eq!(events[1]["data"][0]["account_id"], json!(user_id));
+    assert_eq!(
+        events[1]["data"][0]["paths"],
+        json!([format!("{user_id}/profile/name")])
+    );
+    assert_eq!(
+        events[2]["data"][0]["paths"],
+        json!([format!("{user_id}/profile/about")])
+    );
+
+    Ok(())
+}
+
+/// The paths of the events are compacted, so the logs of a large `:replace` or a large permission
+/// grant fit in the limit of the runtime.
+async fn test_replace_emits_compacted_events() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let items: serde_json::Map<String, Value> = (0..300)
+        .map(|i| (format!("item-{:035}", i), json!("value")))
+        .collect();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "items": items } } }))
+        .deposit(parse_near!("1 N"))
+        .gas(300_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let outcome = user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "items": { ":replace": true } } } }))
+        .gas(300_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let logs_length: usize = outcome.logs().iter().map(|log| log.len()).sum();
+    assert!(logs_length < 16 * 1024);
+    let events: Vec<Value> = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|log| serde_json::from_str(log))
+        .collect::<Result<_, _>>()?;
+    let delete_event = events
+        .iter()
+        .find(|event| event["event"] == "delete")
+        .expect("Missing the delete event");
+    assert_eq!(delete_event["data"][0]["account_id"], json!(user_id));
+    assert_eq!(
+        delete_event["data"][0]["paths"],
+        json!([format!("{user_id}/items")])
+    );
+    assert_eq!(delete_event["data"][0]["truncated"], json!(true));
+
+    // The keys of the granted permission are compacted the same way.
+    let keys: Vec<String> = (0..200)
+        .map(|i| format!("{user_id}/grants/key-{:035}", i))
+        .collect();
+    let outcome = user
+        .call(contract.id(), "grant_write_permission")
+        .args_json(json!({ "predecessor_id": contract.id(), "keys": keys }))
+        .deposit(parse_near!("1 N"))
+        .gas(300_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let logs_length: usize = outcome.logs().iter().map(|log| log.len()).sum();
+    assert!(logs_length < 16 * 1024);
+    let events: Vec<Value> = outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|log| serde_json::from_str(log))
+        .collect::<Result<_, _>>()?;
+    let grant_event = events
+        .iter()
+        .find(|event| event["event"] == "grant_write_permission")
+        .expect("Missing the grant_write_permission event");
+    assert_eq!(
+        grant_event["data"][0]["keys"],
+        json!([format!("{user_id}/grants")])
+    );
+    assert_eq!(grant_event["data"][0]["truncated"], json!(true));
+
+    Ok(())
+}
+
+async fn test_get_changes() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let args = json!({
+        "data": {
+            &user_id: {
+                "profile": {
+                    "name": "Alex",
+                },
+            }
+        }
+    });
+
+    user.call(contract.id(), "set")
+        .args_json(args)
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let changes = user
+        .view(contract.id(), "get_changes")
+        .args_json(json!({ "from_seq": 0 }))
+        .await?
+        .json::<Vec<Value>>()?;
+    let keys: Vec<&str> = changes
+        .iter()
+        .map(|change| change["key"].as_str().unwrap())
+        .collect();
+
+    assert_eq!(keys, vec![user_id.as_str(), "profile", "name"]);
+    assert_eq!(changes[0]["node_id"], json!(0));
+    assert_eq!(changes[2]["seq"], json!(2));
+
+    let changes = user
+        .view(contract.id(), "get_changes")
+        .args_json(json!({ "from_seq": 3 }))
+        .await?
+        .json::<Vec<Value>>()?;
+
+    assert!(changes.is_empty());
+
+    Ok(())
+}
+
+/// The journal entries are charged to the accounts of the changed keys, and their storage is
+/// released when they are pruned.
+async fn test_journal_is_charged_to_accounts() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let other_user = worker.dev_create_account().await?;
+
+    contract
+        .call("set_journal_max_entries")
+        .args_json(json!({ "max_entries": 1 }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let user_id = user.id().to_string();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "a": "" } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    let long_key = "b".repeat(200);
+    let used_bytes = get_account_used_bytes(&contract, &user).await?;
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { &long_key: "" } } }))
+        .transact()
+        .await?
+        .into_result()?;
+    let used_bytes_with_entry = get_account_used_bytes(&contract, &user).await?;
+    // The key is stored in the node and in the journal entry.
+    assert!(used_bytes_with_entry - used_bytes > 400);
+
+    // The entry of the other account prunes the entry of the user.
+    let other_user_id = other_user.id().to_string();
+    other_user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { &other_user_id: { "a": "" } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+    let released_bytes = used_bytes_with_entry - get_account_used_bytes(&contract, &user).await?;
+    assert!(released_bytes > 200);
+
+    Ok(())
+}
+
+async fn test_get_nodes_since() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let set_result = user
+        .call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "profile": { "name": "Alex" } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .json::<Value>()?;
+    let block_height = set_result["block_height"]
+        .as_str()
+        .unwrap()
+        .parse::<u64>()?;
+
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "post": { "main": "Hello" } } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let account_node_id = get_account_node_id(&contract, &user).await?;
+    let node_count = user
+        .view(contract.id(), "get_node_count")
+        .await?
+        .json::<u64>()?;
+    let result = user
+        .view(contract.id(), "get_nodes_since")
+        .args_json(json!({ "block_height": block_height }))
+        .await?
+        .json::<Value>()?;
+    let node_ids: Vec<u64> = result["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| node["node_id"].as_u64().unwrap())
+        .collect();
+
+    // The profile node wasn't changed by the second write.
+    assert_eq!(node_ids, vec![account_node_id, node_count - 1]);
+    assert_eq!(result["pruned"], json!(false));
+
+    // Without the limit, only the first `DEFAULT_NODES_SINCE_LIMIT` journal entries are scanned.
+    let items: serde_json::Map<String, Value> = (0..100)
+        .map(|i| (format!("item-{i}"), json!({ "name": "Item" })))
+        .collect();
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "items": items } } }))
+        .deposit(parse_near!("1 N"))
+        .gas(300_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+
+    let result = user
+        .view(contract.id(), "get_nodes_since")
+        .args_json(json!({ "block_height": 0 }))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(result["next_seq"], json!(100));
+
+    // The next page continues from the returned sequence number, up to the end of the journal.
+    let result = user
+        .view(contract.id(), "get_nodes_since")
+        .args_json(json!({ "block_height": 0, "from_seq": 100, "limit": 1000 }))
+        .await?
+        .json::<Value>()?;
+    let journal_info = user
+        .view(contract.id(), "get_journal_info")
+        .await?
+        .json::<Value>()?;
+    assert!(!result["nodes"].as_array().unwrap().is_empty());
+    assert_eq!(result["next_seq"], journal_info["next_seq"]);
+
+    Ok(())
+}
+
+async fn test_subscription_is_charged_for_matching_writes() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let user_id = user.id().to_string();
+    let pattern = format!("{user_id}/index/notify");
+    user.call(contract.id(), "subscribe")
+        .args_json(json!({ "pattern": pattern, "method_name": "on_write" }))
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The pattern has no wildcards, so it's the literal prefix.
+    let balance = get_subscription_balance(&contract, &user, &pattern).await?;
+
+    for key in ["notify", "other"] {
+        user.call(contract.id(), "set")
+            .args_json(json!({ "data": { &user_id: { "index": { key: "value" } } } }))
+            .deposit(parse_near!("0.1 N"))
+            .gas(300_000_000_000_000)
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    let new_balance = get_subscription_balance(&contract, &user, &pattern).await?;
+
+    // Only the first write matches the pattern. 10 Tgas at the fixed gas price.
+    assert_eq!(balance - new_balance, 10u128.pow(13) * 10u128.pow(8));
+
+    // Changing the gas requires a deposit.
+    let result = user
+        .call(contract.id(), "subscribe")
+        .args_json(
+            json!({ "pattern": pattern, "method_name": "on_write", "gas": "30000000000000" }),
+        )
+        .transact()
+        .await?
+        .into_result();
+    assert!(result.is_err());
+    user.call(contract.id(), "subscribe")
+        .args_json(
+            json!({ "pattern": pattern, "method_name": "on_write", "gas": "30000000000000" }),
+        )
+        .deposit(1)
+        .transact()
+        .await?
+        .into_result()?;
+    let balance = get_subscription_balance(&contract, &user, &pattern).await?;
+
+    // The write doesn't have enough gas left for the call, so the notification is queued.
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: { "index": { "notify": "queued" } } } }))
+        .gas(35_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+    let subscription = get_subscription(&contract, &user, &pattern).await?;
+    assert_eq!(
+        subscription["pending"]["written"][0]["paths"],
+        json!([format!("{user_id}/index/notify")])
+    );
+
+    user.call(contract.id(), "deliver_notifications")
+        .gas(300_000_000_000_000)
+        .transact()
+        .await?
+        .into_result()?;
+    let subscription = get_subscription(&contract, &user, &pattern).await?;
+    assert!(subscription["pending"].is_null());
+    // The storage of the pending notification is returned to the balance. 30 Tgas at the fixed gas
+    // price.
+    let new_balance: u128 = subscription["balance"].as_str().unwrap().parse()?;
+    assert_eq!(balance - new_balance, 30 * 10u128.pow(12) * 10u128.pow(8));
+
+    Ok(())
+}
+
+/// A subscription is called at most once per its interval, so the writes of other accounts can't
+/// drain its balance. The paths written within the interval are queued together.
+async fn test_third_party_cannot_drain_subscription() -> Result<()> {
+    let (worker, contract, user) = init_contract_and_user().await?;
+    let writer = worker.dev_create_account().await?;
+
+    let result = user
+        .call(contract.id(), "subscribe")
+        .args_json(json!({ "pattern": "*/index/notify", "method_name": "on_write" }))
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?
+        .into_result();
+    assert!(result.is_err());
+
+    user.call(contract.id(), "subscribe")
+        .args_json(json!({
+            "pattern": "*/index/notify",
+            "method_name": "on_write",
+            "interval": "1000",
+        }))
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The literal prefix of the pattern is empty.
+    let balance = get_subscription_total(&contract, &user, "").await?;
+
+    let writer_id = writer.id().to_string();
+    for i in 0..3 {
+        writer
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": { &writer_id: { "index": { "notify": i.to_string() } } } }))
+            .deposit(parse_near!("0.1 N"))
+            .gas(300_000_000_000_000)
+            .transact()
+            .await?
+            .into_result()?;
+    }
+
+    // Only the first write schedules a call, the others are within the interval and are queued
+    // together. 10 Tgas at the fixed gas price, while the storage of the pending notification is
+    // moved to the storage deposit.
+    let new_balance = get_subscription_total(&contract, &user, "").await?;
+    assert_eq!(balance - new_balance, 10u128.pow(13) * 10u128.pow(8));
+    let subscription = get_subscription(&contract, &user, "").await?;
+    assert_eq!(
+        subscription["pending"]["written"][0]["paths"],
+        json!([format!("{writer_id}/index/notify")])
+    );
+
+    Ok(())
+}
+
+/// Each of the limits rejects the writes and the permission grants exceeding it, and invalid limits
+/// are rejected.
+async fn test_set_limits() -> Result<()> {
+    let (_, contract, user) = init_contract_and_user().await?;
+
+    let result = contract
+        .call("set_limits")
+        .args_json(json!({ "limits": {
+            "max_value_length": 4,
+            "max_depth": 1,
+            "max_children": 2,
+            "max_keys_per_call": 4,
+        } }))
+        .transact()
+        .await?
+        .into_result();
+    assert!(result.is_err());
+
+    contract
+        .call("set_limits")
+        .args_json(json!({ "limits": {
+            "max_value_length": 4,
+            "max_depth": 3,
+            "max_children": 2,
+            "max_keys_per_call": 4,
+        } }))
+        .transact()
+        .await?
+        .into_result()?;
+
+    let user_id = user.id().to_string();
+    let rejected = [
+        // The value is too long.
+        json!({ &user_id: { "name": "Alice" } }),
+        // The path is too deep.
+        json!({ &user_id: { "profile": { "links": { "x": "1" } } } }),
+        // The node has too many children.
+        json!({ &user_id: { "a": "1", "b": "2", "c": "3" } }),
+        // The data has too many keys.
+        json!({ &user_id: { "a": { "b": "1", "c": "2" }, "d": "3" } }),
+    ];
+    for data in rejected {
+        let result = user
+            .call(contract.id(), "set")
+            .args_json(json!({ "data": data }))
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+
+    // Binary values are measured by their decoded length: 4 bytes take 8 base64 characters.
+    user.call(contract.id(), "set")
+        .args_json(json!({ "data": { &user_id: {
+            "name": "Ali",
+            "thumbnail": { ":base64": "AQIDBA==" },
+        } } }))
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    // The nodes created by `grant_write_permission` are subject to the same limits.
+    let rejected = [
+        // The node has too many children.
+        format!("{user_id}/profile"),
+        // The path is too deep.
+        format!("{user_id}/name/x/y"),
+    ];
+    for key in rejected {
+        let result = user
+            .call(contract.id(), "grant_write_permission")
+            .args_json(json!({ "predecessor_id": contract.id(), "keys": [key] }))
+            .deposit(parse_near!("0.1 N"))
+            .transact()
+            .await?
+            .into_result();
+        assert!(result.is_err());
+    }
+    user.call(contract.id(), "grant_write_permission")
+        .args_json(
+            json!({ "predecessor_id": contract.id(), "keys": [format!("{user_id}/name/x")] }),
+        )
+        .deposit(parse_near!("0.1 N"))
+        .transact()
+        .await?
+        .into_result()?;
+
+    Ok(())
+}
+
+/// Returns the ID of the root node of the account.
+async fn get_account_node_id(contract: &Contract, account: &Account) -> Result<u64> {
+    let result = account
+        .view(contract.id(), "get_account")
+        .args_json(json!({ "account_id": account.id() }))
+        .await?
+        .json::<Value>()?;
+    Ok(result["node_id"].as_u64().unwrap())
+}
+
+/// Returns the number of bytes used by the account.
+async fn get_account_used_bytes(contract: &Contract, account: &Account) -> Result<u64> {
+    let result = account
+        .view(contract.id(), "get_account")
+        .args_json(json!({ "account_id": account.id() }))
+        .await?
+        .json::<Value>()?;
+    Ok(result["used_bytes"].as_u64().unwrap())
+}
+
+/// Returns the balance of the first subscription with the given literal prefix of the pattern.
+async fn get_subscription_balance(
+    contract: &Contract,
+    account: &Account,
+    prefix: &str,
+) -> Result<u128> {
+    let subscription = get_subscription(contract, account, prefix).await?;
+    Ok(subscription["balance"].as_str().unwrap().parse()?)
+}
+
+/// Returns the balance and the storage deposit of the first subscription with the given literal
+/// prefix of the pattern.
+async fn get_subscription_total(
+    contract: &Contract,
+    account: &Account,
+    prefix: &str,
+) -> Result<u128> {
+    let subscription = get_subscription(contract, account, prefix).await?;
+    Ok(subscription["balance"].as_str().unwrap().parse::<u128>()?
+        + subscription["storage_deposit"]
+            .as_str()
+            .unwrap()
+            .parse::<u128>()?)
+}
+
+/// Returns the first subscription with the given literal prefix of the pattern.
+async fn get_subscription(contract: &Contract, account: &Account, prefix: &str) -> Result<Value> {
+    let subscriptions = account
+        .view(contract.id(), "get_subscriptions")
+        .args_json(json!({ "prefix": prefix }))
+        .await?
+        .json::<Vec<Value>>()?;
+    Ok(subscriptions[0].clone())
+}
+
+/// Returns the value of the child of the node with the given key, as returned by `get_node`.
+async fn get_node_child(
+    contract: &Contract,
+    account: &Account,
+    node_id: u64,
+    key: &str,
+    decompress: bool,
+) -> Result<Value> {
+    let node = account
+        .view(contract.id(), "get_node")
+        .args_json(json!({ "node_id": node_id, "decompress": decompress }))
+        .await?
+        .json::<Value>()?;
+    Ok(node["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|child| child[0] == key)
+        .map(|child| child[1].clone())
+        .unwrap_or(Value::Null))
+}
+
 async fn init_contract_and_user() -> Result<(Worker<Sandbox>, Contract, Account)> {
     let workspace_dir = get_workspace_dir();
     let wasm_filepath = workspace_dir.join(CONTRACT_WASM_FILEPATH);
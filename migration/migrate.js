@@ -61,6 +61,55 @@ const config = {
 
   console.log("Num nodes: " + nodes.length);
 
+  // The shared values refer to the blobs, which are counted by the account of the node.
+  const nodesById = {};
+  nodes.forEach((node) => {
+    if (node) {
+      nodesById[node.node_id] = node;
+    }
+  });
+  const nodeAccounts = {};
+  const queue = [];
+  for (const [key, value] of nodesById[0].children) {
+    if (value.Node !== undefined) {
+      nodeAccounts[value.Node] = key;
+      queue.push(value.Node);
+    }
+  }
+  const blobRefCounts = {};
+  while (queue.length > 0) {
+    const nodeId = queue.pop();
+    const accountId = nodeAccounts[nodeId];
+    const node = nodesById[nodeId];
+    for (const [, value] of node ? node.children : []) {
+      if (value.Node !== undefined) {
+        nodeAccounts[value.Node] = accountId;
+        queue.push(value.Node);
+      } else if (value.SharedValue) {
+        const counts = (blobRefCounts[value.SharedValue.hash] =
+          blobRefCounts[value.SharedValue.hash] || {});
+        counts[accountId] = (counts[accountId] || 0) + 1;
+      }
+    }
+  }
+  const blobs = await Promise.all(
+    Object.entries(blobRefCounts).map(async ([hash, counts]) => {
+      const blob = await account.viewFunction(
+        config.inputAccountId,
+        "get_blob",
+        { hash }
+      );
+      return {
+        hash,
+        value: blob.value,
+        owner_id: blob.owner_id,
+        ref_counts: Object.entries(counts),
+      };
+    })
+  );
+
+  console.log("Num blobs: " + blobs.length);
+
   const numAccounts = await account.viewFunction(
     config.inputAccountId,
     "get_account_count"
@@ -112,6 +161,22 @@ const config = {
     });
   }
 
+  const blobInitLimit = 5;
+  for (let i = 0; i < blobs.length; i += blobInitLimit) {
+    const partialBlobs = blobs.slice(i, i + blobInitLimit);
+    console.log(
+      `Initializing blobs from ${i} to ${i + partialBlobs.length} out of ${
+        blobs.length
+      }`
+    );
+    await account.functionCall({
+      contractId: config.outputAccountId,
+      methodName: "genesis_init_blobs",
+      args: { blobs: partialBlobs },
+      gas: GasBoat,
+    });
+  }
+
   for (let i = 0; i < numAccounts; i += initLimit) {
     const partialAccounts = accounts.slice(i, i + initLimit);
     console.log(